@@ -2,6 +2,8 @@ local option = {
     log_level = "Info",
     parallel = 4,
     call_timeout = "1000ms",
+    -- Result codes counted as success, any 2xxx when empty
+    success_codes = {},
     batch_size = "Auto",
     -- duration = "1s",
     -- target_rps = 1,
@@ -12,6 +14,59 @@ local option = {
     log_requests = false,
     log_responses = false,
     protocol = "Diameter",
+    -- round_robin, weighted, least_outstanding or sticky (by Session-Id)
+    distribution = "weighted",
+    peers = {
+        {
+            host = "localhost",
+            port = 3868,
+            -- origin_host = "host.example.com",
+            -- origin_realm = "realm.example.com",
+            -- destination_host = "server.example.com",
+            -- destination_realm = "dest.realm.org",
+            weight = 1,
+            connections = 1,
+        },
+    },
+    -- RFC 6733 TLS over TCP, applied to every peer connection. DTLS over SCTP is not
+    -- supported, transport = "TCP" is the only value
+    tls = {
+        enable = false,
+        transport = "TCP",
+        -- ca_file = "ca.pem",
+        -- cert_file = "client.pem",
+        -- key_file = "client.key",
+        verify = true,
+        -- sni = "server.example.com",
+    },
+    -- Device-Watchdog, DWR is sent after interval (Tw) without traffic. A connection
+    -- whose peer is DOWN is closed, it gets no new requests for the rest of the run
+    watchdog = {
+        enable = true,
+        interval = "30s",
+    },
+    -- Live statistics printed during the run
+    stats = {
+        enable = true,
+        interval = "5s",
+    },
+    -- Final report files, the stats interval sets the resolution of the time series
+    report = {
+        -- json = "report.json",
+        -- csv = "report.csv",
+    },
+    -- Disconnect-Cause sent in DPR when the run ends: rebooting, busy, do_not_want_to_talk_to_you
+    disconnect_cause = "do_not_want_to_talk_to_you",
+    -- Hop-by-Hop and End-to-End Identifiers are allocated per connection as RFC 6733
+    -- recommends, or the same on every run with a seed
+    identifiers = {
+        -- seed = 42,
+    },
+    -- Log each answer failing an assertion, with the reasons
+    log_failed_assertions = false,
+    -- Variables are evaluated for each message, or once per session with scope = "session".
+    -- A session walks the Repeating scenarios in order, ${REQUEST_NUMBER} is the number
+    -- of the request within its session, starting at 0.
     globals = {
         variables = {
             {
@@ -20,14 +75,10 @@ local option = {
                     min = 100000000,
                     max = 900000000,
                     step = 10,
-                },
-            },
-            {
-                CC_REQUEST_NUMBER = {
-                    func = "incremental_counter",
-                    min = 1,
-                    max = 900000000,
-                    step = 1,
+                    scope = "session",
+                    -- With parallel runners: independent (each its own sequence), shared
+                    -- (one sequence taken in turn) or partitioned (a slice of min..max each)
+                    runners = "shared",
                 },
             },
             {
@@ -36,8 +87,48 @@ local option = {
                     min = 1000,
                     max = 9999,
                     step = 1,
+                    scope = "session",
                 }
             },
+            -- custom_script calls the function returned by a Lua chunk, inline or from a file,
+            -- with the session: request_number, variables (session scoped) and captured.
+            -- Locals of the chunk keep their state, separately for each runner.
+            -- {
+            --     MSISDN = {
+            --         func = "custom_script",
+            --         scope = "session",
+            --         script = [[
+            --             local n = 0
+            --             return function(session)
+            --                 n = n + 1
+            --                 return string.format("601%08d", n)
+            --             end
+            --         ]],
+            --         -- file = "msisdn.lua",
+            --     },
+            -- },
+            -- Other generators, numbers are 64-bit and padded with zeros to width:
+            -- { IMSI = { func = "incremental_counter", min = 1010000000000, max = 1019999999999, step = 1, width = 15 } },
+            -- { ID = { func = "uuid" } },
+            -- format is rfc3339 (the default, for Time AVPs), unix, unix_ms or a strftime pattern
+            -- { NOW = { func = "timestamp", format = "unix" } },
+            -- charset is alphanumeric, hex or digits
+            -- { TOKEN = { func = "random_string", length = 16, charset = "hex" } },
+            -- weights are optional, values are picked uniformly without them
+            -- { RAT = { func = "pick", values = { "1000", "1004" }, weights = { 3, 1 } } },
+            -- addresses of the network in order, or at random with order = "random"
+            -- { UE_IP = { func = "ip_address", network = "10.10.0.0/16", runners = "partitioned" } },
+            -- csv_feed reads the rows of a CSV file with a header line, used by column as
+            -- ${SUBSCRIBER.msisdn}. All columns of a message, or of a session with
+            -- scope = "session", come from the same row. order is sequential or random.
+            -- {
+            --     SUBSCRIBER = {
+            --         func = "csv_feed",
+            --         file = "subscribers.csv",
+            --         order = "sequential",
+            --         scope = "session",
+            --     },
+            -- },
         },
     },
     dictionaries = {
@@ -48,8 +139,10 @@ local option = {
         {
             name = "CER",
             type = "Init",
+            -- AVPs of the answer stored in variables, given to the sessions of the connection when captured from the CEA
+            -- capture = { SERVER_HOST = "Origin-Host" },
             message = {
-                command = "Capabilities-Exchange", application = "Base", flags = 0,
+                command = "Capabilities-Exchange", application = "Base",
                 avps = {
                     { name = "Origin-Host", value = "host.example.com" },
                     { name = "Origin-Realm", value = "realm.example.com" },
@@ -64,9 +157,27 @@ local option = {
         {
             name = "Ro-CCR-I",
             type = "Repeating",
+            -- AVPs of the answer used by the next steps of the session as ${NAME}, nested AVPs by path.
+            -- Only the later scenarios may use them, even with times above 1 the first message has
+            -- no answer to take them from, unless an earlier scenario or the CEA captures them too
+            -- capture = { GRANTED_OCTETS = "Multiple-Services-Credit-Control.Granted-Service-Unit.CC-Total-Octets" },
+            -- Checks of the answers, failures are counted per scenario without ending the session.
+            -- An avp alone must be present, present = false requires it absent, equals, matches
+            -- (regex), min and max check its value, error_bit the E flag of the answer.
+            -- assertions = {
+            --     { avp = "Result-Code", min = 2000, max = 2999, error_bit = false },
+            --     { name = "granted", avp = "Multiple-Services-Credit-Control.Granted-Service-Unit" },
+            -- },
             message = {
-                command = "Credit-Control", application = "Charging Control", flags = 0,
+                command = "Credit-Control", application = "Charging Control",
+                -- Command flags, R unless request = false, P with proxiable, E with error and
+                -- T with retransmit. An integer gives the legacy bits as they are, except 0 which
+                -- is R, as the default was always sent as a request
+                -- flags = { proxiable = true },
                 avps = {
+                    -- M, P and V bits of the dictionary overridden by mandatory, protected and
+                    -- vendor, vendor = false sends the AVP without its Vendor-Id
+                    -- { name = "Origin-Host", value = "host.example.com", flags = { mandatory = false } },
                     { name = "Origin-Host", value = "host.example.com" },
                     { name = "Origin-Realm", value = "realm.example.com" },
                     { name = "Product-Name", value = "Petrel" },
@@ -74,9 +185,12 @@ local option = {
                     { name = "Auth-Application-Id", value = "4" },
                     { name = "Service-Identifier", value = "1003" },
                     { name = "Service-Context-Id", value = "1003" },
-                    { name = "CC-Request-Type", value = "1" },
-                    { name = "CC-Request-Number", value = "${CC_REQUEST_NUMBER}" },
+                    -- Enumerated values by number or by the name of their dictionary item
+                    { name = "CC-Request-Type", value = "INITIAL_REQUEST" },
+                    { name = "CC-Request-Number", value = "${REQUEST_NUMBER}" },
                     { name = "Event-Timestamp", value = "2020-01-01T00:00:00Z" },
+                    -- OctetString values are text, or bytes written as hex:0a0b or base64:CgsM
+                    -- { name = "Class", value = "hex:0a0b" },
                     { name = "Subscription-Id",
                         value = {
                             { name = "Subscription-Id-Type", value = "1" },
@@ -104,7 +218,9 @@ local option = {
                             },
                         },
                     },
-                    -- { name = "SGSN-Address", value = "127.0.0.1" }, FIXME: not defined in dict
+                    -- AVPs missing from the dictionaries are given by code, with vendor_id and type
+                    -- when needed. Without a type the value is the raw payload, e.g. hex:0a0b.
+                    { name = "SGSN-Address", code = 1228, vendor_id = 10415, type = "Address", value = "127.0.0.1" },
                     { name = "Requested-Action", value = "0" },
                 },
             },
@@ -112,8 +228,10 @@ local option = {
         {
             name = "Ro-CCR-U",
             type = "Repeating",
+            -- Sent this many times in a row within a session
+            times = 2,
             message = {
-                command = "Credit-Control", application = "Charging Control", flags = 0,
+                command = "Credit-Control", application = "Charging Control",
                 avps = {
                     { name = "Origin-Host", value = "host.example.com" },
                     { name = "Origin-Realm", value = "realm.example.com" },
@@ -122,8 +240,8 @@ local option = {
                     { name = "Auth-Application-Id", value = "4" },
                     { name = "Service-Identifier", value = "1003" },
                     { name = "Service-Context-Id", value = "1003" },
-                    { name = "CC-Request-Type", value = "2" },
-                    { name = "CC-Request-Number", value = "${CC_REQUEST_NUMBER}" },
+                    { name = "CC-Request-Type", value = "UPDATE_REQUEST" },
+                    { name = "CC-Request-Number", value = "${REQUEST_NUMBER}" },
                     { name = "Event-Timestamp", value = "2020-01-01T00:00:00Z" },
                     { name = "Subscription-Id",
                         value = {
@@ -152,7 +270,7 @@ local option = {
                             },
                         },
                     },
-                    -- { name = "SGSN-Address", value = "127.0.0.1" },
+                    { name = "SGSN-Address", code = 1228, vendor_id = 10415, type = "Address", value = "127.0.0.1" },
                     { name = "Requested-Action", value = "0" },
                 },
             },
@@ -161,7 +279,7 @@ local option = {
             name = "Ro-CCR-T",
             type = "Repeating",
             message = {
-                command = "Credit-Control", application = "Charging Control", flags = 0,
+                command = "Credit-Control", application = "Charging Control",
                 avps = {
                     { name = "Origin-Host", value = "host.example.com" },
                     { name = "Origin-Realm", value = "realm.example.com" },
@@ -170,8 +288,8 @@ local option = {
                     { name = "Auth-Application-Id", value = "4" },
                     { name = "Service-Identifier", value = "1003" },
                     { name = "Service-Context-Id", value = "1003" },
-                    { name = "CC-Request-Type", value = "3" },
-                    { name = "CC-Request-Number", value = "${CC_REQUEST_NUMBER}" },
+                    { name = "CC-Request-Type", value = "TERMINATION_REQUEST" },
+                    { name = "CC-Request-Number", value = "${REQUEST_NUMBER}" },
                     { name = "Event-Timestamp", value = "2020-01-01T00:00:00Z" },
                     { name = "Subscription-Id",
                         value = {
@@ -200,7 +318,7 @@ local option = {
                             },
                         },
                     },
-                    -- { name = "SGSN-Address", value = "127.0.0.1" },
+                    { name = "SGSN-Address", code = 1228, vendor_id = 10415, type = "Address", value = "127.0.0.1" },
                     { name = "Requested-Action", value = "0" },
                 },
             },
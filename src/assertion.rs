@@ -0,0 +1,260 @@
+//! Declarative checks of the answers, on their AVPs or E-bit.
+use crate::options;
+use crate::scenario::{self, AvpPath};
+use diameter::dictionary::Dictionary;
+use diameter::flags;
+use diameter::DiameterMessage;
+use regex::Regex;
+use std::error::Error;
+
+pub struct Assertion {
+    name: String,
+    avp: Option<(String, AvpPath)>,
+    present: Option<bool>,
+    equals: Option<String>,
+    matches: Option<Regex>,
+    min: Option<f64>,
+    max: Option<f64>,
+    error_bit: Option<bool>,
+}
+
+impl Assertion {
+    pub fn new(options: &options::Assertion, dict: &Dictionary) -> Result<Self, Box<dyn Error>> {
+        let avp = match &options.avp {
+            Some(path) => Some((path.clone(), AvpPath::new(path, dict)?)),
+            None => None,
+        };
+        let has_value_check = options.equals.is_some()
+            || options.matches.is_some()
+            || options.min.is_some()
+            || options.max.is_some();
+        if avp.is_none() && (options.present.is_some() || has_value_check) {
+            return Err("AVP checks need an avp".into());
+        }
+        if avp.is_none() && options.error_bit.is_none() {
+            return Err("nothing to check, set avp or error_bit".into());
+        }
+        if options.present == Some(false) && has_value_check {
+            return Err("an absent AVP has no value to check".into());
+        }
+        // An AVP without other checks must be present
+        let mut options = options.clone();
+        if avp.is_some() && options.present.is_none() && !has_value_check {
+            options.present = Some(true);
+        }
+        if let (Some(min), Some(max)) = (options.min, options.max) {
+            if min > max {
+                return Err(format!("min {} is greater than max {}", min, max).into());
+            }
+        }
+        let matches = match &options.matches {
+            Some(pattern) => Some(
+                Regex::new(pattern).map_err(|e| format!("invalid regex '{}': {}", pattern, e))?,
+            ),
+            None => None,
+        };
+
+        Ok(Assertion {
+            name: options.name.clone().unwrap_or_else(|| describe(&options)),
+            avp,
+            present: options.present,
+            equals: options.equals.clone(),
+            matches,
+            min: options.min,
+            max: options.max,
+            error_bit: options.error_bit,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns why `answer` fails this assertion, if it does.
+    pub fn check(&self, answer: &DiameterMessage) -> Option<String> {
+        if let Some(expected) = self.error_bit {
+            let set = answer.get_flags() & flags::ERROR != 0;
+            if set != expected {
+                let state = if set { "set" } else { "not set" };
+                return Some(format!("E-bit is {}", state));
+            }
+        }
+
+        let (path, avp) = self.avp.as_ref()?;
+        let value = match (avp.find(answer), self.present) {
+            (Some(_), Some(false)) => return Some(format!("{} is present", path)),
+            (Some(value), _) => value,
+            (None, Some(false)) => return None,
+            (None, _) => return Some(format!("{} is missing", path)),
+        };
+        let value = scenario::avp_value_to_string(value);
+
+        if let Some(expected) = &self.equals {
+            if &value != expected {
+                return Some(format!("{} is '{}', not '{}'", path, value, expected));
+            }
+        }
+        if let Some(regex) = &self.matches {
+            if !regex.is_match(&value) {
+                return Some(format!("{} '{}' does not match '{}'", path, value, regex));
+            }
+        }
+        if self.min.is_some() || self.max.is_some() {
+            let number: f64 = match value.parse() {
+                Ok(number) => number,
+                Err(_) => return Some(format!("{} '{}' is not a number", path, value)),
+            };
+            if self.min.is_some_and(|min| number < min) {
+                return Some(format!("{} {} is below {}", path, value, self.min.unwrap()));
+            }
+            if self.max.is_some_and(|max| number > max) {
+                return Some(format!("{} {} is above {}", path, value, self.max.unwrap()));
+            }
+        }
+        None
+    }
+}
+
+/// Name of an assertion from its checks, e.g. `Result-Code >= 2000 and <= 2999`.
+fn describe(options: &options::Assertion) -> String {
+    let mut checks = vec![];
+    match options.present {
+        Some(true) => checks.push("present".to_string()),
+        Some(false) => checks.push("absent".to_string()),
+        None => {}
+    }
+    if let Some(equals) = &options.equals {
+        checks.push(format!("== '{}'", equals));
+    }
+    if let Some(matches) = &options.matches {
+        checks.push(format!("=~ '{}'", matches));
+    }
+    if let Some(min) = options.min {
+        checks.push(format!(">= {}", min));
+    }
+    if let Some(max) = options.max {
+        checks.push(format!("<= {}", max));
+    }
+
+    let mut name = vec![];
+    if let Some(avp) = &options.avp {
+        name.push(
+            format!("{} {}", avp, checks.join(" and "))
+                .trim_end()
+                .to_string(),
+        );
+    }
+    match options.error_bit {
+        Some(true) => name.push("E-bit set".into()),
+        Some(false) => name.push("E-bit not set".into()),
+        None => {}
+    }
+    name.join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diameter::avp::flags::M;
+    use diameter::avp::{Grouped, UTF8String, Unsigned32, Unsigned64};
+    use diameter::{ApplicationId, CommandCode};
+    use std::sync::Arc;
+
+    fn answer(dict: &Arc<Dictionary>, flags: u8) -> DiameterMessage {
+        let mut answer = DiameterMessage::new(
+            CommandCode::CreditControl,
+            ApplicationId::CreditControl,
+            flags,
+            1,
+            1,
+            Arc::clone(dict),
+        );
+        answer.add_avp(268, None, M, Unsigned32::new(2001).into());
+        answer.add_avp(263, None, M, UTF8String::new("ses;42").into());
+        let octets =
+            diameter::avp::Avp::new(421, None, M, Unsigned64::new(1024).into(), Arc::clone(dict));
+        let gsu = Grouped::new(vec![octets], Arc::clone(dict));
+        answer.add_avp(431, None, M, gsu.into());
+        answer
+    }
+
+    fn assertion(dict: &Dictionary, script: &str) -> Result<Assertion, Box<dyn Error>> {
+        let lua = mlua::Lua::new();
+        let value = lua.load(script).eval().unwrap();
+        let options = mlua::LuaSerdeExt::from_value(&lua, value).unwrap();
+        Assertion::new(&options, dict)
+    }
+
+    #[test]
+    fn test_assertions() {
+        let dict = Arc::new(Dictionary::new(&[&diameter::dictionary::DEFAULT_DICT_XML]));
+        let answer = answer(&dict, 0);
+        let check = |script: &str| assertion(&dict, script).unwrap().check(&answer);
+
+        assert_eq!(check(r#"{ avp = "Session-Id", present = true }"#), None);
+        assert_eq!(check(r#"{ avp = "Granted-Service-Unit" }"#), None);
+        assert_eq!(
+            check(r#"{ avp = "Class", present = true }"#),
+            Some("Class is missing".into())
+        );
+        assert_eq!(check(r#"{ avp = "Class", present = false }"#), None);
+        assert_eq!(check(r#"{ avp = "Result-Code", equals = "2001" }"#), None);
+        assert_eq!(
+            check(r#"{ avp = "Session-Id", equals = "ses;1" }"#),
+            Some("Session-Id is 'ses;42', not 'ses;1'".into())
+        );
+        assert_eq!(
+            check(r#"{ avp = "Session-Id", matches = "^ses;\\d+$" }"#),
+            None
+        );
+        assert_eq!(
+            check(r#"{ avp = "Granted-Service-Unit.CC-Total-Octets", min = 1, max = 1000 }"#),
+            Some("Granted-Service-Unit.CC-Total-Octets 1024 is above 1000".into())
+        );
+        assert_eq!(
+            check(r#"{ avp = "Session-Id", min = 1 }"#),
+            Some("Session-Id 'ses;42' is not a number".into())
+        );
+        assert_eq!(check(r#"{ error_bit = false }"#), None);
+
+        let error = diameter::DiameterMessage::new(
+            CommandCode::CreditControl,
+            ApplicationId::CreditControl,
+            flags::ERROR,
+            1,
+            1,
+            Arc::clone(&dict),
+        );
+        let assertion = assertion(&dict, r#"{ error_bit = false }"#).unwrap();
+        assert_eq!(assertion.check(&error), Some("E-bit is set".into()));
+    }
+
+    #[test]
+    fn test_assertion_names() {
+        let dict = Dictionary::new(&[&diameter::dictionary::DEFAULT_DICT_XML]);
+        let name = |script: &str| assertion(&dict, script).unwrap().name().to_string();
+
+        assert_eq!(
+            name(r#"{ avp = "Result-Code", min = 2000, max = 2999, error_bit = false }"#),
+            "Result-Code >= 2000 and <= 2999, E-bit not set"
+        );
+        assert_eq!(
+            name(r#"{ avp = "Class", present = false }"#),
+            "Class absent"
+        );
+        assert_eq!(name(r#"{ avp = "Class" }"#), "Class present");
+        assert_eq!(
+            name(r#"{ name = "granted", avp = "Granted-Service-Unit" }"#),
+            "granted"
+        );
+
+        let error = |script: &str| assertion(&dict, script).err().unwrap().to_string();
+        assert_eq!(error(r#"{ equals = "1" }"#), "AVP checks need an avp");
+        assert_eq!(error(r#"{}"#), "nothing to check, set avp or error_bit");
+        assert_eq!(
+            error(r#"{ avp = "Session-Id.Result-Code" }"#),
+            "AVP 'Session-Id' is not Grouped"
+        );
+        assert!(error(r#"{ avp = "Session-Id", matches = "(" }"#).starts_with("invalid regex"));
+    }
+}
@@ -0,0 +1,239 @@
+//! Command-line arguments, applied on top of the options file.
+use crate::options::{self, LogLevel, Options, ScenarioType};
+use clap::Parser;
+use std::time::Duration;
+
+#[derive(Parser, Debug)]
+#[command(version, about = "Diameter load generator")]
+pub struct Cli {
+    /// Options file
+    #[arg(short, long, value_name = "PATH", default_value = "./options.lua")]
+    pub config: String,
+
+    /// Override an option of the file, e.g. `--set tls.enable=true`, repeatable
+    #[arg(short = 's', long = "set", value_name = "KEY=VALUE")]
+    pub overrides: Vec<String>,
+
+    /// Requests per second of each runner
+    #[arg(long, value_name = "RPS")]
+    pub target_rps: Option<u32>,
+
+    /// Duration of the run, e.g. `10m`
+    #[arg(long, value_parser = humantime::parse_duration)]
+    pub duration: Option<Duration>,
+
+    /// Number of runners
+    #[arg(long)]
+    pub parallel: Option<u32>,
+
+    /// Peer as `host:port`, replaces the peers of the file, repeatable
+    #[arg(long, value_name = "HOST:PORT", value_parser = parse_peer)]
+    pub peer: Vec<options::Peer>,
+
+    /// off, error, warn, info, debug or trace
+    #[arg(long, value_parser = parse_log_level)]
+    pub log_level: Option<LogLevel>,
+
+    /// Run only the repeating scenarios with this name, repeatable
+    #[arg(long = "scenario", value_name = "NAME")]
+    pub scenarios: Vec<String>,
+
+    /// Write the final report as JSON
+    #[arg(long, value_name = "PATH")]
+    pub json: Option<String>,
+
+    /// Write the final report as CSV
+    #[arg(long, value_name = "PATH")]
+    pub csv: Option<String>,
+
+    /// Print N messages of each scenario without connecting, 1 if N is omitted
+    #[arg(long, value_name = "N", num_args = 0..=1, default_missing_value = "1")]
+    pub dry_run: Option<usize>,
+
+    /// With --dry-run, also print the encoded messages as hex
+    #[arg(long, requires = "dry_run")]
+    pub hex: bool,
+}
+
+impl Cli {
+    pub fn load_options(&self) -> Result<Options, String> {
+        let mut options = options::load(&self.config, &self.overrides)?;
+        self.apply(&mut options)?;
+        Ok(options)
+    }
+
+    fn apply(&self, options: &mut Options) -> Result<(), String> {
+        if let Some(target_rps) = self.target_rps {
+            options.target_rps = target_rps;
+        }
+        if let Some(duration) = self.duration {
+            options.duration = duration;
+        }
+        if let Some(parallel) = self.parallel {
+            options.parallel = parallel;
+        }
+        if !self.peer.is_empty() {
+            options.peers = self.peer.clone();
+        }
+        if let Some(log_level) = self.log_level {
+            options.log_level = log_level;
+        }
+        if self.json.is_some() {
+            options.report.json = self.json.clone();
+        }
+        if self.csv.is_some() {
+            options.report.csv = self.csv.clone();
+        }
+
+        if !self.scenarios.is_empty() {
+            for name in &self.scenarios {
+                if !options.scenarios.iter().any(|s| &s.name == name) {
+                    return Err(format!("Unknown scenario {}", name));
+                }
+            }
+            // Init scenario is always kept, it sends the CER
+            options.scenarios.retain(|s| {
+                s.scenario_type == ScenarioType::Init || self.scenarios.contains(&s.name)
+            });
+        }
+        Ok(())
+    }
+}
+
+fn parse_peer(value: &str) -> Result<options::Peer, String> {
+    let (host, port) = value
+        .rsplit_once(':')
+        .ok_or_else(|| format!("Expected HOST:PORT, got {}", value))?;
+    // IPv6 literals are written in brackets, as [::1]:3868
+    let host = match host.strip_prefix('[').and_then(|h| h.strip_suffix(']')) {
+        Some(host) => host,
+        None if host.contains(':') => {
+            return Err(format!("Expected [HOST]:PORT for IPv6, got {}", value))
+        }
+        None => host,
+    };
+    let port = port
+        .parse()
+        .map_err(|e| format!("Invalid port {}: {}", port, e))?;
+    Ok(options::Peer {
+        host: host.into(),
+        port,
+        origin_host: None,
+        origin_realm: None,
+        destination_host: None,
+        destination_realm: None,
+        weight: 1,
+        connections: 1,
+    })
+}
+
+fn parse_log_level(value: &str) -> Result<LogLevel, String> {
+    match value.to_lowercase().as_str() {
+        "off" => Ok(LogLevel::Off),
+        "error" => Ok(LogLevel::Error),
+        "warn" => Ok(LogLevel::Warn),
+        "info" => Ok(LogLevel::Info),
+        "debug" => Ok(LogLevel::Debug),
+        "trace" => Ok(LogLevel::Trace),
+        _ => Err(format!("Unknown log level {}", value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options() -> Options {
+        options::for_test(
+            r#"
+            scenarios = {
+                { name = "CER", type = "Init", message = { command = "CapabilitiesExchange", application = "Common", avps = {} } },
+                { name = "CCR-I", type = "Repeating", message = { command = "CreditControl", application = "CreditControl", avps = {} } },
+                { name = "CCR-T", type = "Repeating", message = { command = "CreditControl", application = "CreditControl", avps = {} } },
+            },
+            "#,
+        )
+    }
+
+    #[test]
+    fn test_cli_overrides() {
+        let cli = Cli::try_parse_from([
+            "petrel",
+            "--config",
+            "ci.lua",
+            "--target-rps",
+            "2000",
+            "--duration",
+            "5m",
+            "--parallel",
+            "8",
+            "--peer",
+            "10.0.0.1:3868",
+            "--peer",
+            "[::1]:3869",
+            "--log-level",
+            "debug",
+            "--scenario",
+            "CCR-T",
+            "--json",
+            "out/report.json",
+            "-s",
+            "tls.enable=true",
+        ])
+        .unwrap();
+        assert_eq!(cli.config, "ci.lua");
+        assert_eq!(cli.overrides, vec!["tls.enable=true"]);
+        assert_eq!(cli.dry_run, None);
+
+        let dry_run = Cli::try_parse_from(["petrel", "--dry-run", "--hex"]).unwrap();
+        assert_eq!(dry_run.dry_run, Some(1));
+        assert!(dry_run.hex);
+        let dry_run = Cli::try_parse_from(["petrel", "--dry-run", "3"]).unwrap();
+        assert_eq!(dry_run.dry_run, Some(3));
+
+        let mut options = options();
+        cli.apply(&mut options).unwrap();
+        assert_eq!(options.target_rps, 2000);
+        assert_eq!(options.duration, Duration::from_secs(300));
+        assert_eq!(options.parallel, 8);
+        assert_eq!(options.log_level, LogLevel::Debug);
+        assert_eq!(options.peers.len(), 2);
+        assert_eq!(options.peers[0].address(), "10.0.0.1:3868");
+        assert_eq!(options.peers[1].host, "::1");
+        assert_eq!(options.peers[1].address(), "[::1]:3869");
+        assert_eq!(options.report.json, Some("out/report.json".into()));
+        assert_eq!(options.report.csv, None);
+
+        let names: Vec<&str> = options.scenarios.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, vec!["CER", "CCR-T"]);
+    }
+
+    #[test]
+    fn test_cli_defaults() {
+        let cli = Cli::try_parse_from(["petrel"]).unwrap();
+        assert_eq!(cli.config, "./options.lua");
+        assert_eq!(cli.dry_run, None);
+
+        let mut options = options();
+        cli.apply(&mut options).unwrap();
+        assert_eq!(options.target_rps, 100);
+        assert_eq!(options.scenarios.len(), 3);
+        assert_eq!(options.peers[0].address(), "localhost:3868");
+    }
+
+    #[test]
+    fn test_cli_errors() {
+        assert!(Cli::try_parse_from(["petrel", "--peer", "localhost"]).is_err());
+        assert!(Cli::try_parse_from(["petrel", "--peer", "[::1]"]).is_err());
+        assert!(Cli::try_parse_from(["petrel", "--peer", "::1:3868"]).is_err());
+        assert!(Cli::try_parse_from(["petrel", "--log-level", "loud"]).is_err());
+        assert!(Cli::try_parse_from(["petrel", "--duration", "soon"]).is_err());
+        assert!(Cli::try_parse_from(["petrel", "--hex"]).is_err());
+
+        let cli = Cli::try_parse_from(["petrel", "--scenario", "ULR"]).unwrap();
+        assert_eq!(
+            cli.apply(&mut options()),
+            Err("Unknown scenario ULR".to_string())
+        );
+    }
+}
@@ -1,12 +1,19 @@
+use diameter::avp::AvpType;
 use diameter::dictionary;
 use diameter::dictionary::Dictionary;
+use diameter::{ApplicationId, CommandCode};
+use std::collections::{BTreeMap, HashMap};
 use std::error::Error;
 use std::fs;
 use std::path::Path;
+use std::sync::Arc;
 use url::Url;
+use xml::reader::{EventReader, XmlEvent};
 
-pub fn load(filenames: Vec<String>) -> Result<Dictionary, Box<dyn Error>> {
+/// Loads the dictionaries over the default one, with the items of their Enumerated AVPs.
+pub fn load(filenames: Vec<String>) -> Result<(Dictionary, EnumDictionary), Box<dyn Error>> {
     let mut dict = Dictionary::new(&[&dictionary::DEFAULT_DICT_XML]);
+    let mut enums = EnumDictionary::new(&[&dictionary::DEFAULT_DICT_XML]);
 
     for filename in filenames {
         let xml = if Url::parse(&filename).is_ok() {
@@ -14,12 +21,394 @@ pub fn load(filenames: Vec<String>) -> Result<Dictionary, Box<dyn Error>> {
             reqwest::blocking::get(&filename)?.text()?
         } else if Path::new(&filename).exists() {
             log::info!("Loading dictionary from local file: {}", filename);
-            fs::read_to_string(filename)?
+            fs::read_to_string(&filename)?
         } else {
             return Err(format!("File not found: {}", filename).into());
         };
 
+        // Names of Enumerated values are a convenience, numbers still work without them
+        if let Err(e) = enums.load_xml(&xml) {
+            log::warn!("Enumerated values of {} not loaded: {}", filename, e);
+        }
+        check_xml(&xml).map_err(|e| format!("Invalid dictionary {}: {}", filename, e))?;
         dict.load_xml(&xml);
     }
-    Ok(dict)
+    Ok((dict, enums))
+}
+
+/// Checks that a dictionary has the structure the diameter crate reads, as it panics on any
+/// other.
+///
+/// The elements of a kind must come together in their parent, e.g. no `<command>` between
+/// two `<avp>`, with the attributes and the elements the crate requires, and the ids and
+/// codes it knows.
+fn check_xml(xml: &str) -> Result<(), Box<dyn Error>> {
+    // Name of the open elements, with the names of their children in order
+    let mut open: Vec<(String, Vec<String>)> = Vec::new();
+    let mut applications = 0;
+    for event in EventReader::from_str(xml) {
+        match event? {
+            XmlEvent::StartElement {
+                name, attributes, ..
+            } => {
+                let name = name.local_name;
+                let attribute = |key: &str| {
+                    attributes
+                        .iter()
+                        .find(|a| a.name.local_name == key)
+                        .map(|a| a.value.as_str())
+                        .ok_or(format!("<{}> without {}", name, key))
+                };
+                if let Some((parent, children)) = open.last_mut() {
+                    if children.last() != Some(&name) {
+                        if children.contains(&name) {
+                            return Err(format!(
+                                "<{}> elements of <{}> not together",
+                                name, parent
+                            )
+                            .into());
+                        }
+                        children.push(name.clone());
+                    }
+                }
+                match name.as_str() {
+                    "application" if open.len() == 1 => {
+                        applications += 1;
+                        attribute("name")?;
+                        let id = attribute("id")?;
+                        id.parse()
+                            .ok()
+                            .and_then(ApplicationId::from_u32)
+                            .ok_or(format!("Unknown application id: {}", id))?;
+                    }
+                    "command" => {
+                        attribute("short")?;
+                        let command = attribute("name")?;
+                        let code = attribute("code")?;
+                        code.parse()
+                            .ok()
+                            .and_then(CommandCode::from_u32)
+                            .ok_or(format!("Command '{}': unknown code {}", command, code))?;
+                    }
+                    "rule" => {
+                        attribute("avp")?;
+                        attribute("required")?;
+                    }
+                    "avp" => {
+                        let avp = attribute("name")?;
+                        attribute("code")?
+                            .parse::<u32>()
+                            .map_err(|e| format!("AVP '{}': {}", avp, e))?;
+                        if let Ok(vendor_id) = attribute("vendor-id") {
+                            vendor_id
+                                .parse::<u32>()
+                                .map_err(|e| format!("AVP '{}': {}", avp, e))?;
+                        }
+                    }
+                    "data" => {
+                        attribute("type")?;
+                    }
+                    _ => {}
+                }
+                open.push((name, Vec::new()));
+            }
+            XmlEvent::EndElement { .. } => {
+                let (name, children) = open.pop().unwrap_or_default();
+                let required: &[&str] = match name.as_str() {
+                    "command" => &["request", "answer"],
+                    "avp" => &["data"],
+                    _ => &[],
+                };
+                if let Some(child) = required.iter().find(|c| !children.iter().any(|n| n == *c)) {
+                    return Err(format!("<{}> without <{}>", name, child).into());
+                }
+            }
+            _ => {}
+        }
+    }
+    if applications == 0 {
+        return Err("No <application>".into());
+    }
+    Ok(())
+}
+
+/// Type of its name in the dictionaries, e.g. `Unsigned32`.
+pub fn avp_type(name: &str) -> Option<AvpType> {
+    let avp_type = match name {
+        "UTF8String" => AvpType::UTF8String,
+        "OctetString" => AvpType::OctetString,
+        "Integer32" => AvpType::Integer32,
+        "Integer64" => AvpType::Integer64,
+        "Unsigned32" => AvpType::Unsigned32,
+        "Unsigned64" => AvpType::Unsigned64,
+        "Enumerated" => AvpType::Enumerated,
+        "Grouped" => AvpType::Grouped,
+        "DiameterIdentity" => AvpType::Identity,
+        "DiameterURI" => AvpType::DiameterURI,
+        "Time" => AvpType::Time,
+        "Address" => AvpType::Address,
+        "IPv4" => AvpType::AddressIPv4,
+        "IPv6" => AvpType::AddressIPv6,
+        "Float32" => AvpType::Float32,
+        "Float64" => AvpType::Float64,
+        _ => return None,
+    };
+    Some(avp_type)
+}
+
+/// Items of an Enumerated AVP, by name.
+pub type Enums = HashMap<String, i32>;
+
+/// Items of the Enumerated AVPs, which the dictionary of the diameter crate drops, by AVP
+/// code and Vendor-Id.
+#[derive(Debug, Default)]
+pub struct EnumDictionary {
+    avps: BTreeMap<(u32, Option<u32>), Arc<Enums>>,
+}
+
+impl EnumDictionary {
+    /// Items of the Enumerated AVPs of the dictionaries `xmls`, as `Dictionary::new`.
+    pub fn new(xmls: &[&str]) -> Self {
+        let mut enums = EnumDictionary::default();
+        for xml in xmls {
+            if let Err(e) = enums.load_xml(xml) {
+                log::warn!("Enumerated values not loaded: {}", e);
+            }
+        }
+        enums
+    }
+
+    /// Adds the items of the Enumerated AVPs of a dictionary, nothing if it is invalid.
+    ///
+    /// The document is read as a stream of elements, so that the `<avp>` may come in any
+    /// order with the other elements of any number of `<application>`.
+    pub fn load_xml(&mut self, xml: &str) -> Result<(), Box<dyn Error>> {
+        let mut loaded: BTreeMap<(u32, Option<u32>), Enums> = BTreeMap::new();
+        // Name, code and Vendor-Id of the AVP being read
+        let mut avp: Option<(String, u32, Option<u32>)> = None;
+        for event in EventReader::from_str(xml) {
+            match event? {
+                XmlEvent::StartElement {
+                    name, attributes, ..
+                } => {
+                    let attribute = |key: &str| {
+                        attributes
+                            .iter()
+                            .find(|a| a.name.local_name == key)
+                            .map(|a| a.value.as_str())
+                    };
+                    match name.local_name.as_str() {
+                        "avp" => {
+                            let avp_name = attribute("name").unwrap_or_default();
+                            let code = attribute("code")
+                                .ok_or(format!("AVP '{}': no code", avp_name))?
+                                .parse()
+                                .map_err(|e| format!("AVP '{}': {}", avp_name, e))?;
+                            let vendor_id = attribute("vendor-id")
+                                .map(str::parse)
+                                .transpose()
+                                .map_err(|e| format!("AVP '{}': {}", avp_name, e))?;
+                            avp = Some((avp_name.to_string(), code, vendor_id));
+                        }
+                        "item" => {
+                            let Some((avp_name, code, vendor_id)) = &avp else {
+                                continue;
+                            };
+                            let item = attribute("name").unwrap_or_default();
+                            let value = attribute("code")
+                                .ok_or(format!("AVP '{}', item '{}': no code", avp_name, item))?
+                                .parse()
+                                .map_err(|e| {
+                                    format!("AVP '{}', item '{}': {}", avp_name, item, e)
+                                })?;
+                            loaded
+                                .entry((*code, *vendor_id))
+                                .or_default()
+                                .insert(item.to_string(), value);
+                        }
+                        _ => {}
+                    }
+                }
+                XmlEvent::EndElement { name } if name.local_name == "avp" => avp = None,
+                _ => {}
+            }
+        }
+
+        for (key, items) in loaded {
+            let mut enums = self
+                .avps
+                .get(&key)
+                .map(|enums| Enums::clone(enums))
+                .unwrap_or_default();
+            enums.extend(items);
+            self.avps.insert(key, Arc::new(enums));
+        }
+        Ok(())
+    }
+
+    /// Items of the Enumerated AVP `code`, if it has any.
+    pub fn get(&self, code: u32, vendor_id: Option<u32>) -> Option<Arc<Enums>> {
+        self.avps.get(&(code, vendor_id)).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_enums() {
+        let mut enums = EnumDictionary::new(&[&dictionary::DEFAULT_DICT_XML]);
+        let request_type = enums.get(416, None).unwrap();
+        assert_eq!(request_type.get("INITIAL_REQUEST"), Some(&1));
+        assert_eq!(request_type.get("TERMINATION_REQUEST"), Some(&3));
+        assert!(enums.get(263, None).is_none());
+
+        enums
+            .load_xml(
+                r#"
+                <diameter>
+                    <application id="4" name="Test">
+                        <avp name="Test-Enum" code="2901" vendor-id="99901">
+                            <data type="Enumerated">
+                                <item code="-1" name="NEGATIVE"/>
+                                <item code="7" name="SEVEN"/>
+                            </data>
+                        </avp>
+                    </application>
+                </diameter>
+                "#,
+            )
+            .unwrap();
+        let test = enums.get(2901, Some(99901)).unwrap();
+        assert_eq!(test.get("NEGATIVE"), Some(&-1));
+        assert_eq!(test.get("SEVEN"), Some(&7));
+        assert!(enums.get(2901, None).is_none());
+
+        assert!(enums.load_xml("<diameter><application>").is_err());
+        assert!(enums.get(416, None).is_some());
+    }
+
+    #[test]
+    fn test_check_xml() {
+        check_xml(&dictionary::DEFAULT_DICT_XML).unwrap();
+        let error = |xml: &str| check_xml(xml).unwrap_err().to_string();
+        assert_eq!(error("<diameter></diameter>"), "No <application>");
+        assert_eq!(
+            error(r#"<diameter><application id="7" name="Test"/></diameter>"#),
+            "Unknown application id: 7"
+        );
+        assert_eq!(
+            error(
+                r#"<diameter><application id="4" name="Test">
+                    <avp name="Test-Avp" code="2901"/>
+                </application></diameter>"#
+            ),
+            "<avp> without <data>"
+        );
+        assert_eq!(
+            error(
+                r#"<diameter><application id="4" name="Test">
+                    <avp name="Test-Avp" code="x"><data type="Unsigned32"/></avp>
+                </application></diameter>"#
+            ),
+            "AVP 'Test-Avp': invalid digit found in string"
+        );
+        assert_eq!(
+            error(
+                r#"<diameter><application id="4" name="Test">
+                    <command code="272" short="CC" name="Credit-Control"><request/></command>
+                </application></diameter>"#
+            ),
+            "<command> without <answer>"
+        );
+    }
+
+    #[test]
+    fn test_enums_interleaved() {
+        let xml = r#"
+            <diameter>
+                <application id="4" name="Test">
+                    <avp name="First-Enum" code="2901">
+                        <data type="Enumerated">
+                            <item code="1" name="ONE"/>
+                        </data>
+                    </avp>
+                    <command code="272" short="CC" name="Credit-Control">
+                        <request><rule avp="Session-Id" required="true" max="1"/></request>
+                        <answer><rule avp="Session-Id" required="true" max="1"/></answer>
+                    </command>
+                    <avp name="Second-Enum" code="2902">
+                        <data type="Enumerated">
+                            <item code="2" name="TWO"/>
+                        </data>
+                    </avp>
+                </application>
+                <application id="16777238" name="Gx">
+                    <avp name="Third-Enum" code="2903" vendor-id="10415">
+                        <data type="Enumerated">
+                            <item code="3" name="THREE"/>
+                        </data>
+                    </avp>
+                </application>
+                <application id="4" name="Test">
+                    <avp name="First-Enum" code="2901">
+                        <data type="Enumerated">
+                            <item code="4" name="FOUR"/>
+                        </data>
+                    </avp>
+                </application>
+            </diameter>
+            "#;
+        let enums = EnumDictionary::new(&[xml]);
+        let first = enums.get(2901, None).unwrap();
+        assert_eq!(first.get("ONE"), Some(&1));
+        assert_eq!(first.get("FOUR"), Some(&4));
+        assert_eq!(enums.get(2902, None).unwrap().get("TWO"), Some(&2));
+        assert_eq!(enums.get(2903, Some(10415)).unwrap().get("THREE"), Some(&3));
+
+        // Loaded with a dictionary, which the diameter crate reads with the elements of
+        // each kind together only
+        let dir = tempfile::TempDir::new().unwrap();
+        let file = |name: &str, xml: &str| {
+            let path = dir.path().join(name);
+            fs::write(&path, xml).unwrap();
+            path.to_string_lossy().into_owned()
+        };
+        let valid = file(
+            "valid.xml",
+            r#"<diameter><application id="4" name="Test">
+                <avp name="First-Enum" code="2901">
+                    <data type="Enumerated"><item code="1" name="ONE"/></data>
+                </avp>
+            </application></diameter>"#,
+        );
+        let (dict, enums) = load(vec![valid]).unwrap();
+        assert!(dict.get_avp_by_name("First-Enum").is_some());
+        assert_eq!(enums.get(2901, None).unwrap().get("ONE"), Some(&1));
+        let interleaved = file("interleaved.xml", xml);
+        let error = load(vec![interleaved.clone()]).err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "Invalid dictionary {}: <avp> elements of <application> not together",
+                interleaved
+            )
+        );
+
+        // A document which cannot be read adds nothing
+        let enums = EnumDictionary::new(&[r#"
+            <diameter>
+                <application id="4" name="Test">
+                    <avp name="First-Enum" code="2901">
+                        <data type="Enumerated"><item code="1" name="ONE"/></data>
+                    </avp>
+                    <avp name="Bad-Enum" code="2902">
+                        <data type="Enumerated"><item code="x" name="BAD"/></data>
+                    </avp>
+                </application>
+            </diameter>
+            "#]);
+        assert!(enums.get(2901, None).is_none());
+    }
 }
@@ -0,0 +1,253 @@
+//! Renders the messages of each scenario without connecting to any peer.
+use crate::dictionary::EnumDictionary;
+use crate::global::Global;
+use crate::identifiers::Identifiers;
+use crate::options::Options;
+use crate::peer::PeerIdentity;
+use crate::scenario::{self, Session};
+use diameter::dictionary::Dictionary;
+use diameter::DiameterMessage;
+use std::error::Error;
+use std::io::Write;
+use std::sync::Arc;
+
+/// Writes the message of the Init scenario, then `count` sessions walking the call flow
+/// of the repeating scenarios, to `out`. Encoded messages are dumped as hex when `hex`
+/// is set.
+///
+/// Messages get the identity of the first peer and the identifiers of a connection, as
+/// they would when sent to it. There are no answers to capture from, so messages using
+/// captured variables are reported as not rendered.
+pub fn run<W: Write>(
+    options: &Options,
+    dict: Arc<Dictionary>,
+    enums: &EnumDictionary,
+    count: usize,
+    hex: bool,
+    out: &mut W,
+) -> Result<(), Box<dyn Error>> {
+    let global = Global::new(&options.globals)?.with_captures(&options.scenarios);
+    let identity = options
+        .peers
+        .first()
+        .map(PeerIdentity::new)
+        .unwrap_or_default();
+    let mut render = Render {
+        identity,
+        identifiers: Identifiers::new(options.identifiers.seed, 0),
+        dict: Arc::clone(&dict),
+        hex,
+    };
+
+    let mut scenarios = vec![];
+    for scenario in &options.scenarios {
+        let s = scenario::Scenario::new(scenario, &global, Arc::clone(&dict), enums)
+            .map_err(|e| format!("scenario '{}': {}", scenario.name, e))?;
+        scenarios.push((scenario.times as usize, s));
+    }
+    let Some(((_, init), repeating)) = scenarios.split_first_mut() else {
+        return Ok(());
+    };
+
+    let msg = init
+        .next_message(&mut Session::default())
+        .map_err(|e| format!("scenario '{}': {}", init.get_name(), e))?;
+    render.write(&format!("Scenario {}", init.get_name()), msg, out)?;
+
+    let steps: usize = repeating.iter().map(|(times, _)| times).sum();
+    for i in 1..=count {
+        let mut session = Session::default();
+        let mut step = 0;
+        for (times, scenario) in repeating.iter_mut() {
+            for _ in 0..*times {
+                step += 1;
+                let title = format!(
+                    "Session {}/{}, step {}/{}: Scenario {}",
+                    i,
+                    count,
+                    step,
+                    steps,
+                    scenario.get_name()
+                );
+                match scenario.next_message(&mut session) {
+                    Ok(msg) => render.write(&title, msg, out)?,
+                    Err(e) => writeln!(out, "{}\n  Not rendered: {}\n", title, e)?,
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+struct Render {
+    identity: PeerIdentity,
+    identifiers: Identifiers,
+    dict: Arc<Dictionary>,
+    hex: bool,
+}
+
+impl Render {
+    fn write<W: Write>(
+        &mut self,
+        title: &str,
+        msg: DiameterMessage,
+        out: &mut W,
+    ) -> Result<(), Box<dyn Error>> {
+        let identifiers = self.identifiers.next();
+        let msg = self
+            .identity
+            .apply(msg, identifiers, Arc::clone(&self.dict));
+        writeln!(out, "{}", title)?;
+        writeln!(out, "{}", msg)?;
+        if self.hex {
+            let mut encoded = vec![];
+            msg.encode_to(&mut encoded)?;
+            write_hex(&encoded, out)?;
+        }
+        writeln!(out)?;
+        Ok(())
+    }
+}
+
+/// Classic hex dump, 16 bytes per line with their offset.
+fn write_hex<W: Write>(bytes: &[u8], out: &mut W) -> std::io::Result<()> {
+    for (i, chunk) in bytes.chunks(16).enumerate() {
+        let hex: Vec<String> = chunk.iter().map(|b| format!("{:02x}", b)).collect();
+        writeln!(out, "{:08x}  {}", i * 16, hex.join(" "))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options;
+    use diameter::dictionary;
+
+    fn options() -> Options {
+        options::for_test(
+            r#"
+            peers = { { host = "localhost", port = 3868, origin_host = "petrel.example.com" } },
+            globals = {
+                variables = {
+                    { COUNTER = { func = "incremental_counter", min = 1, max = 100, step = 1, scope = "session" } },
+                },
+            },
+            scenarios = {
+                {
+                    name = "CER",
+                    type = "Init",
+                    message = {
+                        command = "Capabilities-Exchange",
+                        application = "Base",
+                        avps = { { name = "Origin-Host", value = "host.example.com" } },
+                    },
+                },
+                {
+                    name = "CCR-I",
+                    type = "Repeating",
+                    capture = { CLASS = "Class" },
+                    message = {
+                        command = "Credit-Control",
+                        application = "Charging Control",
+                        avps = {
+                            { name = "Session-Id", value = "ses;${COUNTER}" },
+                            { name = "Origin-Host", value = "host.example.com" },
+                            { name = "CC-Request-Number", value = "${REQUEST_NUMBER}" },
+                        },
+                    },
+                },
+                {
+                    name = "CCR-U",
+                    type = "Repeating",
+                    times = 2,
+                    message = {
+                        command = "Credit-Control",
+                        application = "Charging Control",
+                        avps = {
+                            { name = "Session-Id", value = "ses;${COUNTER}" },
+                            { name = "CC-Request-Number", value = "${REQUEST_NUMBER}" },
+                        },
+                    },
+                },
+                {
+                    name = "CCR-T",
+                    type = "Repeating",
+                    message = {
+                        command = "Credit-Control",
+                        application = "Charging Control",
+                        avps = { { name = "Class", value = "${CLASS}" } },
+                    },
+                },
+            },
+            "#,
+        )
+    }
+
+    #[test]
+    fn test_dry_run() {
+        let dict = Arc::new(Dictionary::new(&[&dictionary::DEFAULT_DICT_XML]));
+        let enums = EnumDictionary::new(&[&dictionary::DEFAULT_DICT_XML]);
+        let mut out = vec![];
+        run(&options(), dict, &enums, 2, true, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        let titles: Vec<&str> = out.lines().filter(|line| line.starts_with("S")).collect();
+        assert_eq!(
+            titles,
+            vec![
+                "Scenario CER",
+                "Session 1/2, step 1/4: Scenario CCR-I",
+                "Session 1/2, step 2/4: Scenario CCR-U",
+                "Session 1/2, step 3/4: Scenario CCR-U",
+                "Session 1/2, step 4/4: Scenario CCR-T",
+                "Session 2/2, step 1/4: Scenario CCR-I",
+                "Session 2/2, step 2/4: Scenario CCR-U",
+                "Session 2/2, step 3/4: Scenario CCR-U",
+                "Session 2/2, step 4/4: Scenario CCR-T",
+            ]
+        );
+        // One Session-Id per session
+        assert_eq!(out.matches("ses;1").count(), 3);
+        assert_eq!(out.matches("ses;2").count(), 3);
+        // Origin-Host of the peer replaces the one of the template
+        assert!(out.contains("petrel.example.com"));
+        assert!(!out.contains("host.example.com"));
+        // Version 1 followed by the message length
+        assert!(out.contains("00000000  01 00 00"));
+        assert_eq!(
+            out.matches("Not rendered: Variable 'CLASS' has not been captured")
+                .count(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_dry_run_seeded() {
+        let dict = Arc::new(Dictionary::new(&[&dictionary::DEFAULT_DICT_XML]));
+        let enums = EnumDictionary::new(&[&dictionary::DEFAULT_DICT_XML]);
+        let mut options = options();
+        options.identifiers.seed = Some(42);
+        let render = || {
+            let mut out = vec![];
+            run(&options, Arc::clone(&dict), &enums, 1, true, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        // Same identifiers, and bytes, on every run
+        assert_eq!(render(), render());
+
+        let mut identifiers = Identifiers::new(Some(42), 0);
+        let (hop_by_hop, end_to_end) = identifiers.next();
+        assert!(render().contains(&format!("{}, {}", hop_by_hop, end_to_end)));
+    }
+
+    #[test]
+    fn test_write_hex() {
+        let mut out = vec![];
+        write_hex(&(0..18).collect::<Vec<u8>>(), &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "00000000  00 01 02 03 04 05 06 07 08 09 0a 0b 0c 0d 0e 0f\n00000010  10 11\n"
+        );
+    }
+}
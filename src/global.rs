@@ -1,92 +1,809 @@
 use crate::options;
+use crate::scenario;
+use chrono::format::{Item, StrftimeItems};
+use chrono::{SecondsFormat, Utc};
+use diameter::avp::AvpValue;
+use mlua::{Lua, RegistryKey};
+use rand::distributions::{Distribution, WeightedIndex};
 use rand::Rng;
-use std::cell::RefCell;
-use std::collections::HashMap;
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::net::IpAddr;
+use std::ops::Range;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use uuid::Uuid;
 
 pub struct Global {
     variables: HashMap<String, Variable>,
+    feeds: HashMap<String, Feed>,
+    captures: HashSet<String>,
 }
 
 impl Global {
-    pub fn new(options: &options::Global) -> Self {
+    /// Variables of a single runner.
+    pub fn new(options: &options::Global) -> Result<Self, Box<dyn Error>> {
+        Self::new_shared(options, &Shared::new(options, 1))
+    }
+
+    /// Variables of one of the runners, sharing the state in `shared` with the others.
+    pub fn new_shared(options: &options::Global, shared: &Shared) -> Result<Self, Box<dyn Error>> {
         let mut variables = HashMap::new();
+        let mut feeds = HashMap::new();
         for map in &options.variables {
             for (var_name, value) in map {
+                if value.func == options::Function::CsvFeed {
+                    let feed = Feed::new(var_name, value, shared)
+                        .map_err(|e| format!("Variable '{}': {}", var_name, e))?;
+                    feeds.insert(var_name.clone(), feed);
+                    continue;
+                }
+                let function = function(var_name, value, shared)
+                    .map_err(|e| format!("Variable '{}': {}", var_name, e))?;
                 let variable = Variable {
                     name: var_name.clone(),
-                    value: match value.func {
-                        options::Function::IncrementalCounter => Box::new(IncCounter::new(value)),
-                        options::Function::RandomNumber => Box::new(Random::new(value)),
-                        _ => todo!("Function not implemented"),
-                    },
+                    scope: value.scope,
+                    value: function,
                 };
                 variables.insert(var_name.clone(), variable);
             }
         }
-        Global { variables }
+        Ok(Global {
+            variables,
+            feeds,
+            captures: HashSet::new(),
+        })
+    }
+
+    /// Declares the variables captured from answers by the scenarios.
+    pub fn with_captures(mut self, scenarios: &[options::Scenario]) -> Self {
+        for scenario in scenarios {
+            self.captures.extend(scenario.capture.keys().cloned());
+        }
+        self
     }
 
     pub fn get_variable(&self, name: &str) -> Option<&Variable> {
         self.variables.get(name)
     }
+
+    pub fn get_feed(&self, name: &str) -> Option<&Feed> {
+        self.feeds.get(name)
+    }
+
+    pub fn is_captured(&self, name: &str) -> bool {
+        self.captures.contains(name)
+    }
+}
+
+/// State of the variables shared by the parallel runners, for one of them.
+#[derive(Clone)]
+pub struct Shared {
+    runner: usize,
+    runners: usize,
+    counters: Arc<HashMap<String, Arc<AtomicU64>>>,
+}
+
+impl Shared {
+    /// Shared state of `runners` runners, seen by the first one.
+    pub fn new(options: &options::Global, runners: usize) -> Self {
+        let counters = options
+            .variables
+            .iter()
+            .flatten()
+            .filter(|(_, variable)| variable.runners == options::Runners::Shared)
+            .map(|(name, _)| (name.clone(), Arc::default()))
+            .collect();
+        Shared {
+            runner: 0,
+            runners,
+            counters: Arc::new(counters),
+        }
+    }
+
+    /// Index of the runner this state is seen by.
+    pub fn runner(&self) -> usize {
+        self.runner
+    }
+
+    /// The same shared state, seen by runner `runner`.
+    pub fn for_runner(&self, runner: usize) -> Self {
+        Shared {
+            runner,
+            ..self.clone()
+        }
+    }
+}
+
+/// Slice of `0..len` of runner `runner` when partitioned between `runners` runners.
+pub fn partition(len: u64, runner: usize, runners: usize) -> Result<Range<u64>, String> {
+    // In u128, as len may be up to u64::MAX
+    let (len, runner, runners) = (len as u128, runner as u128, runners.max(1) as u128);
+    let bound = |runner: u128| (len * runner / runners) as u64;
+    let range = bound(runner)..bound(runner + 1);
+    if range.is_empty() {
+        return Err(format!(
+            "{} values cannot be partitioned between {} runners",
+            len, runners
+        ));
+    }
+    Ok(range)
+}
+
+/// Cycles through the positions `0..len` of a variable, e.g. the values of a counter or
+/// the rows of a feed, as set by its `runners` option.
+enum Cursor {
+    /// Positions of this runner only
+    Local { next: Cell<u64>, range: Range<u64> },
+    /// Positions taken in turn by all runners
+    Shared { next: Arc<AtomicU64>, len: u64 },
+}
+
+impl Cursor {
+    fn new(
+        name: &str,
+        option: &options::Variable,
+        len: u64,
+        shared: &Shared,
+    ) -> Result<Self, String> {
+        let range = match option.runners {
+            options::Runners::Independent => 0..len,
+            options::Runners::Partitioned => partition(len, shared.runner, shared.runners)?,
+            options::Runners::Shared => {
+                let next = shared.counters.get(name).cloned().unwrap_or_default();
+                return Ok(Cursor::Shared { next, len });
+            }
+        };
+        Ok(Cursor::Local {
+            next: Cell::new(range.start),
+            range,
+        })
+    }
+
+    fn next(&self) -> u64 {
+        match self {
+            Cursor::Local { next, range } => {
+                let position = next.get();
+                next.set(if position + 1 < range.end {
+                    position + 1
+                } else {
+                    range.start
+                });
+                position
+            }
+            Cursor::Shared { next, len } => next.fetch_add(1, Ordering::Relaxed) % len,
+        }
+    }
+
+    /// A random position, among the ones of this runner when partitioned.
+    fn random(&self) -> u64 {
+        let range = match self {
+            Cursor::Local { range, .. } => range.clone(),
+            Cursor::Shared { len, .. } => 0..*len,
+        };
+        rand::thread_rng().gen_range(range)
+    }
 }
 
 pub struct Variable {
     pub name: String,
+    pub scope: options::Scope,
     pub value: Box<dyn Function>,
 }
 
+/// Builds the function generating the values of a variable, csv_feed has none.
+pub fn function(
+    name: &str,
+    option: &options::Variable,
+    shared: &Shared,
+) -> Result<Box<dyn Function>, Box<dyn Error>> {
+    Ok(match option.func {
+        options::Function::IncrementalCounter => Box::new(IncCounter::new(name, option, shared)?),
+        options::Function::RandomNumber => Box::new(Random::new(name, option, shared)?),
+        options::Function::CustomScript => Box::new(Script::new(option)?),
+        options::Function::Uuid => Box::new(RandomUuid),
+        options::Function::Timestamp => Box::new(Timestamp::new(option)?),
+        options::Function::RandomString => Box::new(RandomString::new(option)?),
+        options::Function::Pick => Box::new(Pick::new(option)?),
+        options::Function::IpAddress => Box::new(IpAddress::new(name, option, shared)?),
+        options::Function::CsvFeed => return Err("csv_feed is used by column".into()),
+    })
+}
+
+/// Session a variable is evaluated for.
+pub struct Context<'a> {
+    pub request_number: u32,
+    /// Session scoped variables generated so far
+    pub values: &'a HashMap<String, String>,
+    pub captured: &'a HashMap<String, AvpValue>,
+    /// Captured from the CEA, unless captured again by the session
+    pub init: &'a HashMap<String, AvpValue>,
+}
+
 pub trait Function {
-    fn get(&self) -> String;
+    fn get(&self, context: &Context) -> Result<String, Box<dyn Error>>;
+
+    /// Whether the values may depend on the captured ones.
+    fn reads_captured(&self) -> bool {
+        false
+    }
+}
+
+/// Number of values from `min` to `max` by `step`, at most `u64::MAX`.
+fn range_len(min: i64, max: i64, step: i64) -> u64 {
+    let span = (max as i128 - min as i128).max(0);
+    (span / step.max(1) as i128 + 1).min(u64::MAX as i128) as u64
+}
+
+fn pad(value: impl std::fmt::Display, width: usize) -> String {
+    format!("{:0width$}", value, width = width)
 }
 
+/// From min to max by step, then starting over from min.
 pub struct IncCounter {
-    counter: RefCell<i32>,
-    max: i32,
-    min: i32,
-    step: i32,
+    cursor: Cursor,
+    min: i64,
+    step: i64,
+    width: usize,
 }
 
 impl IncCounter {
-    pub fn new(option: &options::Variable) -> Self {
-        IncCounter {
-            counter: RefCell::new(option.min),
-            max: option.max,
+    pub fn new(name: &str, option: &options::Variable, shared: &Shared) -> Result<Self, String> {
+        let len = range_len(option.min, option.max, option.step);
+        Ok(IncCounter {
+            cursor: Cursor::new(name, option, len, shared)?,
             min: option.min,
-            step: option.step,
-        }
+            step: option.step.max(1),
+            width: option.width,
+        })
     }
 }
 
 impl Function for IncCounter {
-    fn get(&self) -> String {
-        let value = *self.counter.borrow();
-        *self.counter.borrow_mut() += self.step;
-        if *self.counter.borrow() > self.max {
-            *self.counter.borrow_mut() = self.min;
-        }
-        value.to_string()
+    fn get(&self, _context: &Context) -> Result<String, Box<dyn Error>> {
+        let value = self.min as i128 + self.cursor.next() as i128 * self.step as i128;
+        Ok(pad(value, self.width))
     }
 }
 
 pub struct Random {
-    min: i32,
-    max: i32,
+    cursor: Cursor,
+    min: i64,
+    width: usize,
 }
 
 impl Random {
-    pub fn new(option: &options::Variable) -> Self {
-        Random {
+    pub fn new(name: &str, option: &options::Variable, shared: &Shared) -> Result<Self, String> {
+        let len = range_len(option.min, option.max, 1);
+        Ok(Random {
+            cursor: Cursor::new(name, option, len, shared)?,
             min: option.min,
-            max: option.max,
-        }
+            width: option.width,
+        })
     }
 }
 
 impl Function for Random {
-    fn get(&self) -> String {
+    fn get(&self, _context: &Context) -> Result<String, Box<dyn Error>> {
+        let value = self.min as i128 + self.cursor.random() as i128;
+        Ok(pad(value, self.width))
+    }
+}
+
+pub struct RandomUuid;
+
+impl Function for RandomUuid {
+    fn get(&self, _context: &Context) -> Result<String, Box<dyn Error>> {
+        Ok(Uuid::new_v4().to_string())
+    }
+}
+
+/// Current time, by default as RFC 3339 which Time AVPs accept.
+pub enum Timestamp {
+    Rfc3339,
+    /// Seconds since the epoch
+    Unix,
+    /// Milliseconds since the epoch
+    UnixMillis,
+    Strftime(String),
+}
+
+impl Timestamp {
+    pub fn new(option: &options::Variable) -> Result<Self, String> {
+        Ok(match option.format.as_deref() {
+            None | Some("rfc3339") => Timestamp::Rfc3339,
+            Some("unix") => Timestamp::Unix,
+            Some("unix_ms") => Timestamp::UnixMillis,
+            Some(format) => {
+                if StrftimeItems::new(format).any(|item| item == Item::Error) {
+                    return Err(format!("invalid format '{}'", format));
+                }
+                Timestamp::Strftime(format.into())
+            }
+        })
+    }
+}
+
+impl Function for Timestamp {
+    fn get(&self, _context: &Context) -> Result<String, Box<dyn Error>> {
+        let now = Utc::now();
+        Ok(match self {
+            Timestamp::Rfc3339 => now.to_rfc3339_opts(SecondsFormat::Secs, true),
+            Timestamp::Unix => now.timestamp().to_string(),
+            Timestamp::UnixMillis => now.timestamp_millis().to_string(),
+            Timestamp::Strftime(format) => now.format(format).to_string(),
+        })
+    }
+}
+
+pub struct RandomString {
+    charset: &'static [u8],
+    length: usize,
+}
+
+impl RandomString {
+    pub fn new(option: &options::Variable) -> Result<Self, String> {
+        if option.length == 0 {
+            return Err("random_string needs a length".into());
+        }
+        let charset: &[u8] = match option.charset {
+            options::Charset::Alphanumeric => {
+                b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz"
+            }
+            options::Charset::Hex => b"0123456789abcdef",
+            options::Charset::Digits => b"0123456789",
+        };
+        Ok(RandomString {
+            charset,
+            length: option.length,
+        })
+    }
+}
+
+impl Function for RandomString {
+    fn get(&self, _context: &Context) -> Result<String, Box<dyn Error>> {
+        let mut rng = rand::thread_rng();
+        let value = (0..self.length)
+            .map(|_| self.charset[rng.gen_range(0..self.charset.len())] as char)
+            .collect();
+        Ok(value)
+    }
+}
+
+/// One of the values, uniformly or by weight.
+pub struct Pick {
+    values: Vec<String>,
+    weights: Option<WeightedIndex<f64>>,
+}
+
+impl Pick {
+    pub fn new(option: &options::Variable) -> Result<Self, String> {
+        if option.values.is_empty() {
+            return Err("pick needs values".into());
+        }
+        let weights = match option.weights.len() {
+            0 => None,
+            n if n == option.values.len() => Some(
+                WeightedIndex::new(&option.weights)
+                    .map_err(|e| format!("invalid weights: {}", e))?,
+            ),
+            n => return Err(format!("{} weights for {} values", n, option.values.len())),
+        };
+        Ok(Pick {
+            values: option.values.clone(),
+            weights,
+        })
+    }
+}
+
+impl Function for Pick {
+    fn get(&self, _context: &Context) -> Result<String, Box<dyn Error>> {
         let mut rng = rand::thread_rng();
-        let value = rng.gen_range(self.min..=self.max);
-        value.to_string()
+        let index = match &self.weights {
+            Some(weights) => weights.sample(&mut rng),
+            None => rng.gen_range(0..self.values.len()),
+        };
+        Ok(self.values[index].clone())
+    }
+}
+
+/// Addresses of an IPv4 or IPv6 network, in order or at random.
+pub struct IpAddress {
+    cursor: Cursor,
+    order: options::Order,
+    base: IpAddr,
+}
+
+impl IpAddress {
+    pub fn new(name: &str, option: &options::Variable, shared: &Shared) -> Result<Self, String> {
+        let network = option
+            .network
+            .as_ref()
+            .ok_or("ip_address needs a network")?;
+        let invalid = || format!("invalid network '{}'", network);
+        let (address, prefix) = network.split_once('/').ok_or_else(invalid)?;
+        let address: IpAddr = address.parse().map_err(|_| invalid())?;
+        let prefix: u32 = prefix.parse().map_err(|_| invalid())?;
+        let bits = if address.is_ipv4() { 32 } else { 128 };
+        if prefix > bits {
+            return Err(invalid());
+        }
+
+        let host_bits = bits - prefix;
+        let len = if host_bits >= 64 {
+            u64::MAX
+        } else {
+            1 << host_bits
+        };
+        // Host part of the address cleared
+        let base = match address {
+            IpAddr::V4(address) => {
+                let mask = u32::MAX.checked_shl(host_bits).unwrap_or(0);
+                IpAddr::V4((u32::from(address) & mask).into())
+            }
+            IpAddr::V6(address) => {
+                let mask = u128::MAX.checked_shl(host_bits).unwrap_or(0);
+                IpAddr::V6((u128::from(address) & mask).into())
+            }
+        };
+        Ok(IpAddress {
+            cursor: Cursor::new(name, option, len, shared)?,
+            order: option.order,
+            base,
+        })
+    }
+}
+
+impl Function for IpAddress {
+    fn get(&self, _context: &Context) -> Result<String, Box<dyn Error>> {
+        let offset = match self.order {
+            options::Order::Sequential => self.cursor.next(),
+            options::Order::Random => self.cursor.random(),
+        };
+        let address = match self.base {
+            IpAddr::V4(base) => IpAddr::V4((u32::from(base) + offset as u32).into()),
+            IpAddr::V6(base) => IpAddr::V6((u128::from(base) + offset as u128).into()),
+        };
+        Ok(address.to_string())
+    }
+}
+
+/// Value returned by a Lua function, defined by the chunk of `script` or `file`.
+///
+/// The chunk runs once and returns the function, which is then called for each value
+/// with a table of the session: `request_number`, `variables` for the session scoped
+/// variables generated so far and `captured`. Locals of the chunk keep their state
+/// between calls, each runner has its own.
+pub struct Script {
+    lua: Lua,
+    function: RegistryKey,
+}
+
+impl Script {
+    pub fn new(option: &options::Variable) -> Result<Self, Box<dyn Error>> {
+        let (name, source) = match (&option.script, &option.file) {
+            (Some(script), None) => ("script".to_string(), script.clone()),
+            (None, Some(file)) => {
+                let source = std::fs::read_to_string(file)
+                    .map_err(|e| format!("cannot read '{}': {}", file, e))?;
+                (file.clone(), source)
+            }
+            _ => return Err("custom_script needs either script or file".into()),
+        };
+
+        let lua = Lua::new();
+        let function = match lua.load(&source).set_name(name).eval() {
+            Ok(mlua::Value::Function(function)) => lua.create_registry_value(function)?,
+            Ok(other) => {
+                return Err(format!(
+                    "the script must return a function, not {}",
+                    other.type_name()
+                )
+                .into())
+            }
+            Err(e) => return Err(lua_error(e).into()),
+        };
+        Ok(Script { lua, function })
+    }
+
+    fn context<'lua>(&'lua self, context: &Context) -> mlua::Result<mlua::Table<'lua>> {
+        let table = self.lua.create_table()?;
+        table.set("request_number", context.request_number)?;
+        table.set("variables", context.values.clone())?;
+        let captured: HashMap<&str, String> = context
+            .init
+            .iter()
+            .chain(context.captured)
+            .map(|(name, value)| (name.as_str(), scenario::avp_value_to_string(value)))
+            .collect();
+        table.set("captured", captured)?;
+        Ok(table)
+    }
+}
+
+impl Function for Script {
+    fn get(&self, context: &Context) -> Result<String, Box<dyn Error>> {
+        let function: mlua::Function = self.lua.registry_value(&self.function)?;
+        let value = self
+            .context(context)
+            .and_then(|context| function.call(context))
+            .map_err(lua_error)?;
+        match value {
+            mlua::Value::String(s) => Ok(s.to_str()?.to_string()),
+            mlua::Value::Integer(i) => Ok(i.to_string()),
+            mlua::Value::Number(n) => Ok(n.to_string()),
+            other => Err(format!(
+                "the function must return a string, not {}",
+                other.type_name()
+            )
+            .into()),
+        }
+    }
+
+    fn reads_captured(&self) -> bool {
+        true
+    }
+}
+
+/// Rows of a CSV file with a header line, used as `${NAME.column}`.
+///
+/// A row is taken for each message, or for each session with scope = "session", so the
+/// columns used by a message or a session all come from the same row.
+pub struct Feed {
+    pub name: String,
+    pub scope: options::Scope,
+    order: options::Order,
+    columns: Vec<String>,
+    rows: Vec<Vec<String>>,
+    cursor: Cursor,
+}
+
+impl Feed {
+    pub fn new(
+        name: &str,
+        option: &options::Variable,
+        shared: &Shared,
+    ) -> Result<Self, Box<dyn Error>> {
+        let file = option.file.as_ref().ok_or("csv_feed needs a file")?;
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_path(file)
+            .map_err(|e| format!("cannot read '{}': {}", file, e))?;
+        let columns = reader
+            .headers()
+            .map_err(|e| format!("'{}': {}", file, e))?
+            .iter()
+            .map(String::from)
+            .collect();
+        let mut rows = vec![];
+        for record in reader.records() {
+            let record = record.map_err(|e| format!("'{}': {}", file, e))?;
+            rows.push(record.iter().map(String::from).collect());
+        }
+        if rows.is_empty() {
+            return Err(format!("'{}' has no rows", file).into());
+        }
+        let cursor = Cursor::new(name, option, rows.len() as u64, shared)?;
+
+        Ok(Feed {
+            name: name.into(),
+            scope: option.scope,
+            order: option.order,
+            columns,
+            rows,
+            cursor,
+        })
+    }
+
+    pub fn column(&self, name: &str) -> Option<usize> {
+        self.columns.iter().position(|column| column == name)
+    }
+
+    pub fn next_row(&self) -> usize {
+        let row = match self.order {
+            options::Order::Sequential => self.cursor.next(),
+            options::Order::Random => self.cursor.random(),
+        };
+        row as usize
+    }
+
+    pub fn cell(&self, row: usize, column: usize) -> &str {
+        &self.rows[row][column]
+    }
+}
+
+/// Message of a Lua error, without its stack traceback.
+fn lua_error(e: mlua::Error) -> String {
+    let message = match e {
+        mlua::Error::CallbackError { cause, .. } => cause.to_string(),
+        e => e.to_string(),
+    };
+    message.lines().next().unwrap_or_default().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counter(runners: options::Runners) -> options::Variable {
+        options::Variable {
+            func: options::Function::IncrementalCounter,
+            min: 10,
+            max: 19,
+            step: 1,
+            scope: options::Scope::Message,
+            runners,
+            ..Default::default()
+        }
+    }
+
+    fn variable(func: options::Function) -> options::Variable {
+        options::Variable {
+            func,
+            ..Default::default()
+        }
+    }
+
+    fn values(variable: &Variable, count: usize) -> Vec<String> {
+        let context = Context {
+            request_number: 0,
+            values: &HashMap::new(),
+            captured: &HashMap::new(),
+            init: &HashMap::new(),
+        };
+        (0..count)
+            .map(|_| variable.value.get(&context).unwrap())
+            .collect()
+    }
+
+    fn runners(runners: options::Runners) -> Vec<Global> {
+        let options = options::Global {
+            variables: vec![[("COUNTER".into(), counter(runners))].into()],
+        };
+        let shared = Shared::new(&options, 3);
+        (0..3)
+            .map(|runner| Global::new_shared(&options, &shared.for_runner(runner)).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_runners() {
+        let counters = runners(options::Runners::Independent);
+        let counter = |runner: usize| counters[runner].get_variable("COUNTER").unwrap();
+        assert_eq!(values(counter(0), 2), ["10", "11"]);
+        assert_eq!(values(counter(1), 2), ["10", "11"]);
+
+        let counters = runners(options::Runners::Shared);
+        let counter = |runner: usize| counters[runner].get_variable("COUNTER").unwrap();
+        assert_eq!(values(counter(0), 2), ["10", "11"]);
+        assert_eq!(values(counter(1), 2), ["12", "13"]);
+        assert_eq!(
+            values(counter(2), 7),
+            ["14", "15", "16", "17", "18", "19", "10"]
+        );
+
+        let counters = runners(options::Runners::Partitioned);
+        let counter = |runner: usize| counters[runner].get_variable("COUNTER").unwrap();
+        assert_eq!(values(counter(0), 4), ["10", "11", "12", "10"]);
+        assert_eq!(values(counter(1), 4), ["13", "14", "15", "13"]);
+        assert_eq!(values(counter(2), 5), ["16", "17", "18", "19", "16"]);
+    }
+
+    fn generate(option: options::Variable, count: usize) -> Result<Vec<String>, String> {
+        let shared = Shared::new(&options::Global { variables: vec![] }, 1);
+        let variable = Variable {
+            name: "X".into(),
+            scope: option.scope,
+            value: function("X", &option, &shared).map_err(|e| e.to_string())?,
+        };
+        Ok(values(&variable, count))
+    }
+
+    #[test]
+    fn test_generators() {
+        let imsi = options::Variable {
+            min: 1010000000000,
+            max: 1019999999999,
+            step: 1,
+            width: 15,
+            ..Default::default()
+        };
+        assert_eq!(
+            generate(imsi, 2).unwrap(),
+            ["001010000000000", "001010000000001"]
+        );
+
+        let uuid = generate(variable(options::Function::Uuid), 1).unwrap();
+        assert!(Uuid::parse_str(&uuid[0]).is_ok());
+
+        let timestamp = |format: &str| options::Variable {
+            func: options::Function::Timestamp,
+            format: Some(format.into()),
+            ..Default::default()
+        };
+        let now = generate(variable(options::Function::Timestamp), 1).unwrap();
+        assert!(now[0].parse::<chrono::DateTime<Utc>>().is_ok());
+        let unix = generate(timestamp("unix_ms"), 1).unwrap();
+        assert!(unix[0].parse::<u64>().unwrap() > 1_600_000_000_000);
+        assert_eq!(generate(timestamp("%Y"), 1).unwrap()[0].len(), 4);
+        assert_eq!(
+            generate(timestamp("%Q"), 1).err().unwrap(),
+            "invalid format '%Q'"
+        );
+
+        let hex = options::Variable {
+            func: options::Function::RandomString,
+            length: 8,
+            charset: options::Charset::Hex,
+            ..Default::default()
+        };
+        let hex = generate(hex, 1).unwrap();
+        assert_eq!(hex[0].len(), 8);
+        assert!(hex[0].chars().all(|c| c.is_ascii_hexdigit()));
+
+        let pick = |weights: Vec<f64>| options::Variable {
+            func: options::Function::Pick,
+            values: vec!["a".into(), "b".into()],
+            weights,
+            ..Default::default()
+        };
+        assert_eq!(generate(pick(vec![1.0, 0.0]), 3).unwrap(), ["a", "a", "a"]);
+        assert!(generate(pick(vec![]), 10)
+            .unwrap()
+            .iter()
+            .all(|v| v == "a" || v == "b"));
+        assert_eq!(
+            generate(pick(vec![1.0]), 1).err().unwrap(),
+            "1 weights for 2 values"
+        );
+    }
+
+    #[test]
+    fn test_ip_address() {
+        let network = |network: &str| options::Variable {
+            func: options::Function::IpAddress,
+            network: Some(network.into()),
+            ..Default::default()
+        };
+        assert_eq!(
+            generate(network("10.0.0.5/30"), 5).unwrap(),
+            ["10.0.0.4", "10.0.0.5", "10.0.0.6", "10.0.0.7", "10.0.0.4"]
+        );
+        assert_eq!(
+            generate(network("2001:db8::/64"), 2).unwrap(),
+            ["2001:db8::", "2001:db8::1"]
+        );
+        assert_eq!(generate(network("0.0.0.0/0"), 1).unwrap(), ["0.0.0.0"]);
+        assert_eq!(
+            generate(network("10.0.0.0/33"), 1).err().unwrap(),
+            "invalid network '10.0.0.0/33'"
+        );
+
+        let mut random = network("192.168.1.0/24");
+        random.order = options::Order::Random;
+        for address in generate(random, 20).unwrap() {
+            assert!(address.starts_with("192.168.1."));
+        }
+    }
+
+    #[test]
+    fn test_partition() {
+        assert_eq!(partition(10, 0, 1), Ok(0..10));
+        assert_eq!(partition(10, 2, 3), Ok(6..10));
+        assert_eq!(partition(u64::MAX, 1, 2), Ok(u64::MAX / 2..u64::MAX));
+        assert_eq!(
+            partition(2, 0, 3),
+            Err("2 values cannot be partitioned between 3 runners".into())
+        );
+
+        let mut random = counter(options::Runners::Partitioned);
+        random.func = options::Function::RandomNumber;
+        let shared = Shared::new(&options::Global { variables: vec![] }, 2);
+        let random = Random::new("RANDOM", &random, &shared.for_runner(1)).unwrap();
+        for _ in 0..20 {
+            let value = random.cursor.random() as i64 + random.min;
+            assert!((15..=19).contains(&value));
+        }
     }
 }
@@ -0,0 +1,87 @@
+//! Hop-by-Hop and End-to-End Identifiers of the requests sent on a connection.
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Allocates identifiers as RFC 6733 section 3 recommends. The Hop-by-Hop Identifier
+/// increases from a random start. The End-to-End Identifier starts with the low 12 bits
+/// of the current time in its high bits and random low 20 bits, then increases.
+///
+/// With a seed, the start values come from the seed and `stream` only, so that each
+/// stream gets the same identifiers on every run.
+pub struct Identifiers {
+    hop_by_hop: u32,
+    end_to_end: u32,
+}
+
+impl Identifiers {
+    pub fn new(seed: Option<u64>, stream: u64) -> Self {
+        let (mut rng, time) = match seed {
+            Some(seed) => {
+                let mut rng =
+                    StdRng::seed_from_u64(seed ^ stream.wrapping_mul(0x9e37_79b9_7f4a_7c15));
+                let time = rng.gen::<u32>();
+                (rng, time)
+            }
+            None => {
+                let time = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_secs() as u32)
+                    .unwrap_or_default();
+                (StdRng::from_entropy(), time)
+            }
+        };
+        Identifiers {
+            hop_by_hop: rng.gen(),
+            end_to_end: (time & 0xfff) << 20 | rng.gen::<u32>() & 0xf_ffff,
+        }
+    }
+
+    /// Hop-by-Hop and End-to-End Identifiers of the next request.
+    pub fn next(&mut self) -> (u32, u32) {
+        let identifiers = (self.hop_by_hop, self.end_to_end);
+        self.hop_by_hop = self.hop_by_hop.wrapping_add(1);
+        self.end_to_end = self.end_to_end.wrapping_add(1);
+        identifiers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_identifiers() {
+        let mut identifiers = Identifiers::new(None, 0);
+        let (hop_by_hop, end_to_end) = identifiers.next();
+        assert_eq!(
+            identifiers.next(),
+            (hop_by_hop.wrapping_add(1), end_to_end.wrapping_add(1))
+        );
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as u32;
+        // The clock may tick in between
+        let high = end_to_end >> 20;
+        assert!(high == time & 0xfff || high == (time.wrapping_sub(1)) & 0xfff);
+
+        let mut identifiers = Identifiers {
+            hop_by_hop: u32::MAX,
+            end_to_end: u32::MAX,
+        };
+        identifiers.next();
+        assert_eq!(identifiers.next(), (0, 0));
+    }
+
+    #[test]
+    fn test_seeded_identifiers() {
+        let sequence = |seed, stream| {
+            let mut identifiers = Identifiers::new(Some(seed), stream);
+            (0..3).map(|_| identifiers.next()).collect::<Vec<_>>()
+        };
+        assert_eq!(sequence(42, 0), sequence(42, 0));
+        assert_ne!(sequence(42, 0), sequence(42, 1));
+        assert_ne!(sequence(42, 0), sequence(43, 0));
+    }
+}
@@ -1,18 +1,35 @@
+mod assertion;
+mod cli;
 mod dictionary;
+mod dryrun;
 mod global;
+mod identifiers;
 mod options;
+mod peer;
+mod report;
 mod runner;
 mod scenario;
+mod stats;
+mod transport;
+mod validate;
+mod watchdog;
 
 use chrono::Local;
+use clap::Parser;
 use std::io::Write;
+use std::sync::Arc;
 use std::thread;
 use tokio::sync::mpsc;
+use tokio::sync::oneshot;
+use tokio::sync::watch;
 
 #[tokio::main]
 async fn main() {
-    // Load Config file
-    let options = options::load("./options.lua");
+    // Load Config file, with the command-line overrides
+    let cli = cli::Cli::parse();
+    let options = cli
+        .load_options()
+        .unwrap_or_else(|e| exit_with_errors(&[e]));
 
     env_logger::Builder::new()
         .format(|buf, record| {
@@ -36,10 +53,52 @@ async fn main() {
 
     log::debug!("Options is {:?}", options);
 
+    // Dictionaries may be fetched with a blocking HTTP client
+    let dictionaries = options.dictionaries.clone();
+    let (dict, enums) = tokio::task::spawn_blocking(move || {
+        dictionary::load(dictionaries).map_err(|e| format!("Failed to load dictionaries: {}", e))
+    })
+    .await
+    .unwrap()
+    .unwrap_or_else(|e| exit_with_errors(&[e]));
+
+    if let Err(errors) = validate::validate(&options, &dict, &enums) {
+        exit_with_errors(&errors);
+    }
+    let dict = Arc::new(dict);
+    let enums = Arc::new(enums);
+
+    if let Some(count) = cli.dry_run {
+        let mut out = std::io::stdout().lock();
+        if let Err(e) = dryrun::run(&options, dict, &enums, count, cli.hex, &mut out) {
+            exit_with_errors(&[e.to_string()]);
+        }
+        return;
+    }
+
+    // Stop the runners gracefully on SIGINT/SIGTERM, a second signal exits immediately
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    tokio::spawn(async move {
+        wait_for_signal().await;
+        log::warn!("Signal received, stopping runners");
+        let _ = shutdown_tx.send(true);
+        wait_for_signal().await;
+        log::warn!("Signal received again, exiting");
+        std::process::exit(130);
+    });
+
     // Runners
     let (tx, mut rx) = mpsc::channel(8);
-    for _ in 0..options.parallel {
+    let mut live_stats = vec![];
+    let shared = global::Shared::new(&options.globals, options.parallel as usize);
+    for runner in 0..options.parallel as usize {
+        let shared = shared.for_runner(runner);
         let tx = tx.clone();
+        let shutdown_rx = shutdown_rx.clone();
+        let dict = Arc::clone(&dict);
+        let enums = Arc::clone(&enums);
+        let live = Arc::new(stats::LiveStats::default());
+        live_stats.push(Arc::clone(&live));
         let options = options.clone();
         let param = runner::RunParameter::new(&options);
         tokio::task::spawn_blocking(move || {
@@ -49,7 +108,8 @@ async fn main() {
                 .unwrap();
 
             rt.block_on(async move {
-                let report = runner::run(options, param).await;
+                let report =
+                    runner::run(options, param, dict, enums, shared, live, shutdown_rx).await;
                 tx.send(report).await.unwrap();
             });
         });
@@ -57,13 +117,58 @@ async fn main() {
 
     drop(tx);
 
-    let mut total_rps = 0f64;
-    let mut elapsed = tokio::time::Duration::from_secs(0);
+    // Live statistics, also kept as the time series of the report
+    let (stop_tx, mut stop_rx) = oneshot::channel::<()>();
+    let mut reporter = stats::LiveReporter::new(live_stats);
+    let mut interval = tokio::time::interval(options.stats.interval);
+    let log_stats = options.stats.enable;
+    let live_reporter = tokio::spawn(async move {
+        let mut series = vec![];
+        interval.tick().await;
+        loop {
+            tokio::select! {
+                _ = &mut stop_rx => break,
+                _ = interval.tick() => {}
+            }
+            let line = reporter.tick();
+            if log_stats {
+                log::info!("Live: {}", line);
+            }
+            series.push(line);
+        }
+        series
+    });
+
+    let mut summary = report::Summary::new(options);
     while let Some(report) = rx.recv().await {
-        total_rps += report.rps;
-        elapsed = elapsed.max(report.elapsed);
+        summary.add(report);
+    }
+
+    let _ = stop_tx.send(());
+    summary.set_series(live_reporter.await.unwrap());
+
+    summary.log();
+    summary.write();
+}
+
+fn exit_with_errors(errors: &[String]) -> ! {
+    for error in errors {
+        eprintln!("error: {}", error);
     }
+    std::process::exit(1);
+}
+
+#[cfg(unix)]
+async fn wait_for_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut sigterm = signal(SignalKind::terminate()).unwrap();
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = sigterm.recv() => {}
+    }
+}
 
-    log::info!("Total RPS: {}", total_rps);
-    log::info!("Elapsed: {:?}", elapsed);
+#[cfg(not(unix))]
+async fn wait_for_signal() {
+    let _ = tokio::signal::ctrl_c().await;
 }
@@ -1,6 +1,5 @@
 use std::collections::HashMap;
 
-use mlua::prelude::LuaSerdeExt;
 use mlua::UserData;
 use serde::{Deserialize, Serialize};
 use std::time::Duration;
@@ -10,15 +9,43 @@ pub struct Options {
     pub log_level: LogLevel,
     pub parallel: u32,
     pub target_rps: u32,
-    #[serde(deserialize_with = "humantime_duration_deserializer")]
+    #[serde(
+        serialize_with = "humantime_duration_serializer",
+        deserialize_with = "humantime_duration_deserializer"
+    )]
     pub call_timeout: Duration,
-    #[serde(deserialize_with = "humantime_duration_deserializer")]
+    /// Result codes counted as success, any 2xxx when empty
+    #[serde(default)]
+    pub success_codes: Vec<u32>,
+    #[serde(
+        serialize_with = "humantime_duration_serializer",
+        deserialize_with = "humantime_duration_deserializer"
+    )]
     pub duration: Duration,
     pub batch_size: BatchSize,
     pub log_requests: bool,
     pub log_responses: bool,
+    /// Log the answers failing an assertion
+    #[serde(default)]
+    pub log_failed_assertions: bool,
     pub globals: Global,
     pub protocol: Protocol,
+    #[serde(default = "default_peers")]
+    pub peers: Vec<Peer>,
+    #[serde(default)]
+    pub distribution: Distribution,
+    #[serde(default)]
+    pub tls: Tls,
+    #[serde(default)]
+    pub watchdog: Watchdog,
+    #[serde(default)]
+    pub disconnect_cause: DisconnectCause,
+    #[serde(default)]
+    pub stats: Stats,
+    #[serde(default)]
+    pub report: Report,
+    #[serde(default)]
+    pub identifiers: Identifiers,
     pub dictionaries: Vec<String>,
     pub scenarios: Vec<Scenario>,
 }
@@ -33,9 +60,9 @@ pub enum LogLevel {
     Trace,
 }
 
-impl Into<log::LevelFilter> for LogLevel {
-    fn into(self) -> log::LevelFilter {
-        match self {
+impl From<LogLevel> for log::LevelFilter {
+    fn from(level: LogLevel) -> Self {
+        match level {
             LogLevel::Off => log::LevelFilter::Off,
             LogLevel::Error => log::LevelFilter::Error,
             LogLevel::Warn => log::LevelFilter::Warn,
@@ -59,12 +86,232 @@ pub enum Protocol {
     HTTP2,
 }
 
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Peer {
+    pub host: String,
+    pub port: u16,
+    pub origin_host: Option<String>,
+    pub origin_realm: Option<String>,
+    pub destination_host: Option<String>,
+    pub destination_realm: Option<String>,
+    #[serde(default = "default_peer_weight")]
+    pub weight: u32,
+    #[serde(default = "default_peer_connections")]
+    pub connections: u32,
+}
+
+impl Peer {
+    /// `host:port`, with IPv6 literals in brackets as `[::1]:3868`.
+    pub fn address(&self) -> String {
+        if self.host.contains(':') {
+            format!("[{}]:{}", self.host, self.port)
+        } else {
+            format!("{}:{}", self.host, self.port)
+        }
+    }
+}
+
+fn default_peers() -> Vec<Peer> {
+    vec![Peer {
+        host: "localhost".into(),
+        port: 3868,
+        origin_host: None,
+        origin_realm: None,
+        destination_host: None,
+        destination_realm: None,
+        weight: default_peer_weight(),
+        connections: default_peer_connections(),
+    }]
+}
+
+fn default_peer_weight() -> u32 {
+    1
+}
+
+fn default_peer_connections() -> u32 {
+    1
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Copy, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum Distribution {
+    RoundRobin,
+    #[default]
+    Weighted,
+    LeastOutstanding,
+    Sticky,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Tls {
+    #[serde(default)]
+    pub enable: bool,
+    pub ca_file: Option<String>,
+    pub cert_file: Option<String>,
+    pub key_file: Option<String>,
+    #[serde(default = "default_tls_verify")]
+    pub verify: bool,
+    pub sni: Option<String>,
+    /// Only "TCP", DTLS over SCTP is not supported
+    #[serde(default = "default_tls_transport")]
+    pub transport: String,
+}
+
+impl Default for Tls {
+    fn default() -> Self {
+        Tls {
+            enable: false,
+            ca_file: None,
+            cert_file: None,
+            key_file: None,
+            verify: default_tls_verify(),
+            sni: None,
+            transport: default_tls_transport(),
+        }
+    }
+}
+
+fn default_tls_verify() -> bool {
+    true
+}
+
+fn default_tls_transport() -> String {
+    "TCP".into()
+}
+
+/// Device-Watchdog settings, `interval` is Tw from RFC 3539.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Watchdog {
+    #[serde(default = "default_watchdog_enable")]
+    pub enable: bool,
+    #[serde(
+        default = "default_watchdog_interval",
+        serialize_with = "humantime_duration_serializer",
+        deserialize_with = "humantime_duration_deserializer"
+    )]
+    pub interval: Duration,
+}
+
+impl Default for Watchdog {
+    fn default() -> Self {
+        Watchdog {
+            enable: default_watchdog_enable(),
+            interval: default_watchdog_interval(),
+        }
+    }
+}
+
+fn default_watchdog_enable() -> bool {
+    true
+}
+
+fn default_watchdog_interval() -> Duration {
+    Duration::from_secs(30)
+}
+
+/// Live statistics printed every `interval` during the run.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Stats {
+    #[serde(default = "default_stats_enable")]
+    pub enable: bool,
+    #[serde(
+        default = "default_stats_interval",
+        serialize_with = "humantime_duration_serializer",
+        deserialize_with = "humantime_duration_deserializer"
+    )]
+    pub interval: Duration,
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Stats {
+            enable: default_stats_enable(),
+            interval: default_stats_interval(),
+        }
+    }
+}
+
+fn default_stats_enable() -> bool {
+    true
+}
+
+fn default_stats_interval() -> Duration {
+    Duration::from_secs(5)
+}
+
+/// Hop-by-Hop and End-to-End Identifiers of the requests, reproducible between runs
+/// with a `seed`.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Clone)]
+pub struct Identifiers {
+    pub seed: Option<u64>,
+}
+
+/// Files the final report is written to, in addition to the log.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Clone)]
+pub struct Report {
+    pub json: Option<String>,
+    pub csv: Option<String>,
+}
+
+/// Disconnect-Cause sent in the DPR at the end of the run.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Copy, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum DisconnectCause {
+    Rebooting,
+    Busy,
+    #[default]
+    DoNotWantToTalkToYou,
+}
+
+impl DisconnectCause {
+    pub fn code(&self) -> i32 {
+        match self {
+            DisconnectCause::Rebooting => 0,
+            DisconnectCause::Busy => 1,
+            DisconnectCause::DoNotWantToTalkToYou => 2,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct Scenario {
     pub name: String,
     #[serde(rename = "type")]
     pub scenario_type: ScenarioType,
+    /// Times the message is sent in a row within a session, e.g. CCR-U
+    #[serde(default = "default_times")]
+    pub times: u32,
     pub message: Message,
+    /// Session variables set from the answer, by AVP path such as `Granted-Service-Unit.CC-Time`
+    #[serde(default)]
+    pub capture: HashMap<String, String>,
+    /// Checks of the answer, failures are counted per scenario
+    #[serde(default)]
+    pub assertions: Vec<Assertion>,
+}
+
+/// Check of an answer, either on an AVP or on the E-bit.
+///
+/// Value checks apply to the text of the AVP value, `min` and `max` to its number.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Clone)]
+pub struct Assertion {
+    /// Name in the report, made from the checks when not set
+    pub name: Option<String>,
+    /// AVP path, nested AVPs separated by dots
+    pub avp: Option<String>,
+    /// Whether the AVP must be present or absent
+    pub present: Option<bool>,
+    pub equals: Option<String>,
+    /// Regular expression
+    pub matches: Option<String>,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    /// Whether the E-bit must be set or not
+    pub error_bit: Option<bool>,
+}
+
+fn default_times() -> u32 {
+    1
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
@@ -77,13 +324,80 @@ pub enum ScenarioType {
 pub struct Message {
     pub command: String,
     pub application: String,
+    #[serde(default)]
+    pub flags: MessageFlags,
     pub avps: Vec<Avp>,
 }
 
+/// Command flags of the header, by name or as the legacy integer.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[serde(untagged)]
+pub enum MessageFlags {
+    /// Bits as given, except the historical `flags = 0` which always sent requests, so R
+    Bits(u8),
+    Named(NamedFlags),
+}
+
+impl Default for MessageFlags {
+    fn default() -> Self {
+        MessageFlags::Named(NamedFlags::default())
+    }
+}
+
+/// Command flags by name, a request unless `request = false`.
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct NamedFlags {
+    #[serde(default = "default_request")]
+    pub request: bool,
+    #[serde(default)]
+    pub proxiable: bool,
+    #[serde(default)]
+    pub error: bool,
+    #[serde(default)]
+    pub retransmit: bool,
+}
+
+impl Default for NamedFlags {
+    fn default() -> Self {
+        NamedFlags {
+            request: default_request(),
+            proxiable: false,
+            error: false,
+            retransmit: false,
+        }
+    }
+}
+
+fn default_request() -> bool {
+    true
+}
+
+/// AVP of a message, from the dictionary by `name` or raw by `code`.
+///
+/// `vendor_id` and `type` override those of the dictionary, a raw AVP has no Vendor-Id
+/// and is an OctetString unless set.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Clone)]
 pub struct Avp {
+    #[serde(default)]
     pub name: String,
     pub value: Value,
+    #[serde(default)]
+    pub flags: AvpFlags,
+    pub code: Option<u32>,
+    pub vendor_id: Option<u32>,
+    /// Type name as in the dictionaries, e.g. `Unsigned32`
+    #[serde(rename = "type")]
+    pub avp_type: Option<String>,
+}
+
+/// Overrides of the AVP flags, the dictionary decides the M and V bits when not set.
+///
+/// The V bit goes with the Vendor-Id: `vendor = false` sends the AVP without it.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Clone)]
+pub struct AvpFlags {
+    pub vendor: Option<bool>,
+    pub mandatory: Option<bool>,
+    pub protected: Option<bool>,
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
@@ -93,6 +407,12 @@ pub enum Value {
     Avp(Vec<Avp>),
 }
 
+impl Default for Value {
+    fn default() -> Self {
+        Value::String(String::new())
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct Global {
     pub variables: Vec<HashMap<String, Variable>>,
@@ -100,33 +420,224 @@ pub struct Global {
 
 // TODO Different function should have different fields
 // eg. random_number should not have 'step' field
-#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Clone)]
 pub struct Variable {
     pub func: Function,
-    pub min: i32,
-    pub max: i32,
-    pub step: i32,
+    #[serde(default)]
+    pub min: i64,
+    #[serde(default)]
+    pub max: i64,
+    #[serde(default)]
+    pub step: i64,
+    /// Numbers are padded with zeros to this width
+    #[serde(default)]
+    pub width: usize,
+    #[serde(default)]
+    pub scope: Scope,
+    /// Lua chunk returning the function of a custom_script
+    #[serde(default)]
+    pub script: Option<String>,
+    /// File holding the chunk of a custom_script, instead of `script`, or the rows of a csv_feed
+    #[serde(default)]
+    pub file: Option<String>,
+    /// How the rows of a csv_feed, or the addresses of an ip_address, are taken
+    #[serde(default)]
+    pub order: Order,
+    #[serde(default)]
+    pub runners: Runners,
+    /// Format of a timestamp: rfc3339, unix, unix_ms or a strftime pattern
+    #[serde(default)]
+    pub format: Option<String>,
+    /// Length of a random_string
+    #[serde(default)]
+    pub length: usize,
+    #[serde(default)]
+    pub charset: Charset,
+    /// Values of a pick
+    #[serde(default)]
+    pub values: Vec<String>,
+    /// Relative weights of the values of a pick, uniform when empty
+    #[serde(default)]
+    pub weights: Vec<f64>,
+    /// Network of an ip_address, e.g. `10.0.0.0/16`
+    #[serde(default)]
+    pub network: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+/// Characters of a random_string.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Copy, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum Charset {
+    #[default]
+    Alphanumeric,
+    /// Lowercase hexadecimal digits
+    Hex,
+    Digits,
+}
+
+/// How the values of a variable are split between the parallel runners.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Copy, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum Runners {
+    /// Each runner goes through all the values on its own
+    #[default]
+    Independent,
+    /// The runners take the next value in turn, as a single counter
+    Shared,
+    /// Each runner goes through its own slice of the values
+    Partitioned,
+}
+
+/// Order in which the rows of a csv_feed are taken.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Copy, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum Order {
+    /// One after the other, starting over after the last one
+    #[default]
+    Sequential,
+    Random,
+}
+
+/// How long the value of a variable is kept.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Copy, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum Scope {
+    /// A new value for each message
+    #[default]
+    Message,
+    /// A new value for each session, kept by all the messages of its call flow
+    Session,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Clone)]
 #[serde(rename_all = "snake_case")]
 pub enum Function {
+    #[default]
     IncrementalCounter,
     RandomNumber,
     CustomScript,
+    CsvFeed,
+    /// Random UUID (v4)
+    Uuid,
+    /// Current time
+    Timestamp,
+    RandomString,
+    /// One of `values`, at random
+    Pick,
+    /// Address of `network`
+    IpAddress,
 }
 
 impl UserData for Options {}
 
-pub fn load(filename: &str) -> Options {
+/// Loads the options file, then applies `overrides` to the returned table.
+///
+/// Overrides are `key=value` assignments, where the key is a dotted path such as
+/// `tls.enable` or `peers.1.port`, and the value a Lua expression. Values which are
+/// not valid expressions, such as `10s`, are taken as strings.
+pub fn load(filename: &str, overrides: &[String]) -> Result<Options, String> {
     let lua = mlua::Lua::new();
-    let lua_script = std::fs::read_to_string(filename).expect("Failed to read options file");
+    let lua_script = std::fs::read_to_string(filename)
+        .map_err(|e| format!("Failed to read options file {}: {}", filename, e))?;
     let value = lua
         .load(&lua_script)
+        .set_name(filename)
         .eval()
-        .expect("Failed to load options");
-    let options: Options = lua.from_value(value).expect("Failed to convert options");
-    options
+        .map_err(|e| format!("Failed to load options file {}: {}", filename, e))?;
+    for assignment in overrides {
+        apply_override(&lua, &value, assignment).map_err(|e| format!("Invalid override: {}", e))?;
+    }
+    deserialize(value)
+}
+
+/// The options every file must set, the fixture of the tests.
+#[cfg(test)]
+const MINIMAL: &str = r#"{
+    log_level = "Info",
+    parallel = 1,
+    target_rps = 100,
+    batch_size = "Auto",
+    call_timeout = "1s",
+    duration = "10s",
+    log_requests = false,
+    log_responses = false,
+    protocol = "Diameter",
+    globals = { variables = {} },
+    dictionaries = {},
+    scenarios = {},
+}"#;
+
+/// The minimal options with the fields of a Lua table on top, e.g. `parallel = 2`, for the
+/// tests of all modules.
+#[cfg(test)]
+pub(crate) fn for_test(fields: &str) -> Options {
+    let lua = mlua::Lua::new();
+    let options: mlua::Table = lua.load(MINIMAL).eval().unwrap();
+    let fields: mlua::Table = lua.load(format!("{{ {} }}", fields)).eval().unwrap();
+    for field in fields.pairs::<mlua::Value, mlua::Value>() {
+        let (key, value) = field.unwrap();
+        options.set(key, value).unwrap();
+    }
+    deserialize(mlua::Value::Table(options)).unwrap()
+}
+
+/// Converts the options table, errors tell which option is wrong, e.g. `peers[0].port`.
+fn deserialize(value: mlua::Value) -> Result<Options, String> {
+    let deserializer = mlua::serde::Deserializer::new(value);
+    serde_path_to_error::deserialize(deserializer).map_err(|e| {
+        let path = e.path().to_string();
+        match e.into_inner() {
+            mlua::Error::DeserializeError(msg) => format!("Invalid option {}: {}", path, msg),
+            e => format!("Invalid option {}: {}", path, e),
+        }
+    })
+}
+
+fn apply_override(lua: &mlua::Lua, options: &mlua::Value, assignment: &str) -> mlua::Result<()> {
+    let error = |msg: &str| mlua::Error::RuntimeError(format!("{}: {}", msg, assignment));
+    let (path, value) = assignment
+        .split_once('=')
+        .ok_or_else(|| error("Expected key=value"))?;
+
+    let value = match lua.load(format!("return {}", value)).eval::<mlua::Value>() {
+        Ok(value) if !value.is_nil() => value,
+        _ => mlua::Value::String(lua.create_string(value)?),
+    };
+
+    let mut table = match options {
+        mlua::Value::Table(table) => table.clone(),
+        _ => return Err(error("Options is not a table")),
+    };
+    let keys: Vec<mlua::Value> = path
+        .trim()
+        .split('.')
+        .map(|key| match key.parse::<i64>() {
+            Ok(index) => Ok(mlua::Value::Integer(index)),
+            Err(_) if !key.is_empty() => Ok(mlua::Value::String(lua.create_string(key)?)),
+            Err(_) => Err(error("Invalid key")),
+        })
+        .collect::<mlua::Result<_>>()?;
+
+    let (last, parents) = keys.split_last().unwrap();
+    for key in parents {
+        table = match table.get::<_, mlua::Value>(key.clone())? {
+            mlua::Value::Table(child) => child,
+            mlua::Value::Nil => {
+                let child = lua.create_table()?;
+                table.set(key.clone(), child.clone())?;
+                child
+            }
+            _ => return Err(error("Not a table")),
+        };
+    }
+    table.set(last.clone(), value)
+}
+
+fn humantime_duration_serializer<S>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    serializer.serialize_str(&humantime::format_duration(*duration).to_string())
 }
 
 fn humantime_duration_deserializer<'de, D>(deserializer: D) -> Result<Duration, D::Error>
@@ -134,12 +645,14 @@ where
     D: serde::Deserializer<'de>,
 {
     let s = String::deserialize(deserializer)?;
-    humantime::parse_duration(&s).map_err(|e| serde::de::Error::custom(e.to_string()))
+    humantime::parse_duration(&s)
+        .map_err(|e| serde::de::Error::custom(format!("invalid duration '{}': {}", s, e)))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use mlua::prelude::LuaSerdeExt;
 
     #[test]
     fn test_load_options() -> mlua::Result<()> {
@@ -151,11 +664,29 @@ mod tests {
                     parallel = 4,
                     target_rps = 20000, 
                     batch_size = 5,
-                    call_timeout = "1000ms", 
+                    call_timeout = "1000ms",
+                    success_codes = { 2001, 2002 },
                     duration = "60s", 
                     log_requests = false,
                     log_responses = false,
                     protocol = "Diameter",
+                    distribution = "least_outstanding",
+                    tls = {
+                        enable = true,
+                        ca_file = "ca.pem",
+                        cert_file = "client.pem",
+                        key_file = "client.key",
+                        sni = "ocs.example.com",
+                    },
+                    watchdog = { interval = "6s" },
+                    disconnect_cause = "rebooting",
+                    stats = { enable = false, interval = "2s" },
+                    report = { json = "report.json", csv = "report.csv" },
+                    identifiers = { seed = 42 },
+                    peers = {
+                        { host = "127.0.0.1", port = 3868, origin_host = "petrel.example.com", weight = 3, connections = 2 },
+                        { host = "ocs.example.com", port = 3869, destination_realm = "ocs.example.com" },
+                    },
                     globals = {
                         variables = {
                             {
@@ -164,6 +695,7 @@ mod tests {
                                     min = 1,
                                     max = 1000000000,
                                     step = 1,
+                                    scope = "session",
                                 },
                             },
                         },
@@ -176,10 +708,12 @@ mod tests {
                             name = "CER",
                             type = "Init",
                             message = {
-                                command = "Capability-Exchange", application = "Common", flags = 0,
+                                command = "Capability-Exchange", application = "Common",
+                                flags = { proxiable = true },
                                 avps = {
                                     { name = "Origin-Host", value = "host.example.com" },
-                                    { name = "Origin-Realm", value = "realm.example.com" },
+                                    { name = "Origin-Realm", value = "realm.example.com",
+                                      flags = { mandatory = false, protected = true } },
                                 },
                             },
                         },
@@ -196,8 +730,8 @@ mod tests {
         assert_eq!(options.batch_size, BatchSize::Fixed(5));
         assert_eq!(options.call_timeout, Duration::from_millis(1000));
         assert_eq!(options.duration, Duration::from_secs(60));
-        assert_eq!(options.log_requests, false);
-        assert_eq!(options.log_responses, false);
+        assert!(!options.log_requests);
+        assert!(!options.log_responses);
         assert_eq!(options.globals.variables.len(), 1);
         let expected_variables: HashMap<String, Variable> = [(
             "COUNTER".to_string(),
@@ -206,11 +740,56 @@ mod tests {
                 min: 1,
                 max: 1000000000,
                 step: 1,
+                scope: Scope::Session,
+                ..Default::default()
             },
         )]
         .into();
         assert_eq!(options.globals.variables[0], expected_variables);
         assert_eq!(options.protocol, Protocol::Diameter);
+        assert_eq!(options.peers.len(), 2);
+        assert_eq!(
+            options.peers[0],
+            Peer {
+                host: "127.0.0.1".into(),
+                port: 3868,
+                origin_host: Some("petrel.example.com".into()),
+                origin_realm: None,
+                destination_host: None,
+                destination_realm: None,
+                weight: 3,
+                connections: 2,
+            }
+        );
+        assert_eq!(options.peers[1].address(), "ocs.example.com:3869");
+        assert_eq!(options.peers[1].weight, 1);
+        assert_eq!(options.peers[1].connections, 1);
+        assert_eq!(options.distribution, Distribution::LeastOutstanding);
+        assert!(options.watchdog.enable);
+        assert_eq!(options.watchdog.interval, Duration::from_secs(6));
+        assert_eq!(options.disconnect_cause, DisconnectCause::Rebooting);
+        assert_eq!(options.identifiers.seed, Some(42));
+        assert_eq!(options.success_codes, vec![2001, 2002]);
+        assert!(!options.stats.enable);
+        assert_eq!(options.stats.interval, Duration::from_secs(2));
+        assert_eq!(options.report.json, Some("report.json".into()));
+        assert_eq!(options.report.csv, Some("report.csv".into()));
+        assert_eq!(
+            options.tls,
+            Tls {
+                enable: true,
+                ca_file: Some("ca.pem".into()),
+                cert_file: Some("client.pem".into()),
+                key_file: Some("client.key".into()),
+                verify: true,
+                sni: Some("ocs.example.com".into()),
+                transport: "TCP".into(),
+            }
+        );
+        assert_eq!(
+            options.peers[1].destination_realm,
+            Some("ocs.example.com".into())
+        );
         assert_eq!(options.dictionaries.len(), 1);
         assert_eq!(options.dictionaries[0], "diameter.xml");
         assert_eq!(options.scenarios.len(), 1);
@@ -219,17 +798,31 @@ mod tests {
             Scenario {
                 name: "CER".into(),
                 scenario_type: ScenarioType::Init,
+                times: 1,
+                capture: HashMap::new(),
+                assertions: vec![],
                 message: Message {
                     command: "Capability-Exchange".into(),
                     application: "Common".into(),
+                    flags: MessageFlags::Named(NamedFlags {
+                        proxiable: true,
+                        ..Default::default()
+                    }),
                     avps: vec![
                         Avp {
                             name: "Origin-Host".into(),
                             value: Value::String("host.example.com".into()),
+                            ..Default::default()
                         },
                         Avp {
                             name: "Origin-Realm".into(),
                             value: Value::String("realm.example.com".into()),
+                            flags: AvpFlags {
+                                vendor: None,
+                                mandatory: Some(false),
+                                protected: Some(true),
+                            },
+                            ..Default::default()
                         },
                     ],
                 },
@@ -238,4 +831,114 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_default_peers() {
+        let options = for_test("");
+
+        assert_eq!(options.peers.len(), 1);
+        assert_eq!(options.peers[0].address(), "localhost:3868");
+        assert_eq!(options.peers[0].weight, 1);
+        let mut ipv6 = options.peers[0].clone();
+        ipv6.host = "::1".into();
+        assert_eq!(ipv6.address(), "[::1]:3868");
+        assert_eq!(options.distribution, Distribution::Weighted);
+        assert_eq!(options.tls, Tls::default());
+        assert_eq!(options.watchdog, Watchdog::default());
+        assert_eq!(
+            options.disconnect_cause,
+            DisconnectCause::DoNotWantToTalkToYou
+        );
+        assert!(options.success_codes.is_empty());
+        assert_eq!(options.stats, Stats::default());
+        assert_eq!(options.report, Report::default());
+    }
+
+    #[test]
+    fn test_legacy_message_flags() -> mlua::Result<()> {
+        let lua = mlua::Lua::new();
+        let message = |flags: &str| -> mlua::Result<Message> {
+            let value = lua
+                .load(format!(
+                    r#"{{
+                        command = "Capability-Exchange", application = "Common", flags = {},
+                        avps = {{ {{ name = "Origin-Host", value = "host.example.com" }} }},
+                    }}"#,
+                    flags
+                ))
+                .eval()?;
+            lua.from_value(value)
+        };
+        assert_eq!(message("0")?.flags, MessageFlags::Bits(0));
+        assert_eq!(
+            message("{ request = false }")?.flags,
+            MessageFlags::Named(NamedFlags {
+                request: false,
+                ..Default::default()
+            })
+        );
+        assert!(message("\"R\"").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_apply_override() -> mlua::Result<()> {
+        let lua = mlua::Lua::new();
+        let value = lua
+            .load(
+                r#"
+                return {
+                    target_rps = 100,
+                    log_level = "Info",
+                    peers = { { host = "localhost", port = 3868 } },
+                }
+                "#,
+            )
+            .eval()?;
+
+        apply_override(&lua, &value, "target_rps=250")?;
+        apply_override(&lua, &value, "log_level=Debug")?;
+        apply_override(&lua, &value, "call_timeout=2s")?;
+        apply_override(&lua, &value, "peers.1.port=3869")?;
+        apply_override(&lua, &value, "tls.enable=true")?;
+        apply_override(&lua, &value, "success_codes={2001, 2002}")?;
+        assert!(apply_override(&lua, &value, "target_rps").is_err());
+        assert!(apply_override(&lua, &value, "target_rps.x=1").is_err());
+        assert!(apply_override(&lua, &value, "peers..port=1").is_err());
+
+        let table = match value {
+            mlua::Value::Table(table) => table,
+            _ => unreachable!(),
+        };
+        assert_eq!(table.get::<_, u32>("target_rps")?, 250);
+        assert_eq!(table.get::<_, String>("log_level")?, "Debug");
+        assert_eq!(table.get::<_, String>("call_timeout")?, "2s");
+        let peers: mlua::Table = table.get("peers")?;
+        assert_eq!(peers.get::<_, mlua::Table>(1)?.get::<_, u16>("port")?, 3869);
+        let tls: mlua::Table = table.get("tls")?;
+        assert!(tls.get::<_, bool>("enable")?);
+        let success_codes: Vec<u32> = table.get("success_codes")?;
+        assert_eq!(success_codes, vec![2001, 2002]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_options() -> mlua::Result<()> {
+        let lua = mlua::Lua::new();
+        let minimal = || lua.load(MINIMAL).eval::<mlua::Table>();
+        assert!(deserialize(mlua::Value::Table(minimal()?)).is_ok());
+
+        let options = minimal()?;
+        options.set("call_timeout", "soon")?;
+        let error = deserialize(mlua::Value::Table(options)).unwrap_err();
+        assert!(error.starts_with("Invalid option call_timeout: invalid duration 'soon'"));
+
+        let options = minimal()?;
+        options.set("peers", lua.load("{ { host = 1 } }").eval::<mlua::Table>()?)?;
+        let error = deserialize(mlua::Value::Table(options)).unwrap_err();
+        assert!(error.starts_with("Invalid option peers[0].host: "));
+
+        Ok(())
+    }
 }
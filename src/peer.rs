@@ -0,0 +1,393 @@
+use crate::options;
+use crate::options::Distribution;
+use diameter::avp::flags::{M, P};
+use diameter::avp::Avp;
+use diameter::avp::AvpValue;
+use diameter::avp::Identity;
+use diameter::dictionary::Dictionary;
+use diameter::{CommandCode, DiameterMessage};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+pub const SESSION_ID: u32 = 263;
+pub const ORIGIN_HOST: u32 = 264;
+pub const ORIGIN_REALM: u32 = 296;
+pub const DESTINATION_HOST: u32 = 293;
+pub const DESTINATION_REALM: u32 = 283;
+
+/// Identity AVPs configured on a peer, overriding the ones in the scenario messages.
+#[derive(Debug, Default, Clone)]
+pub struct PeerIdentity {
+    pub origin_host: Option<String>,
+    pub origin_realm: Option<String>,
+    pub destination_host: Option<String>,
+    pub destination_realm: Option<String>,
+}
+
+impl PeerIdentity {
+    pub fn new(options: &options::Peer) -> Self {
+        PeerIdentity {
+            origin_host: options.origin_host.clone(),
+            origin_realm: options.origin_realm.clone(),
+            destination_host: options.destination_host.clone(),
+            destination_realm: options.destination_realm.clone(),
+        }
+    }
+
+    pub fn get(&self, code: u32) -> Option<&str> {
+        match code {
+            ORIGIN_HOST => self.origin_host.as_deref(),
+            ORIGIN_REALM => self.origin_realm.as_deref(),
+            DESTINATION_HOST => self.destination_host.as_deref(),
+            DESTINATION_REALM => self.destination_realm.as_deref(),
+            _ => None,
+        }
+    }
+
+    pub fn overrides(&self) -> Vec<(u32, &str)> {
+        [
+            ORIGIN_HOST,
+            ORIGIN_REALM,
+            DESTINATION_HOST,
+            DESTINATION_REALM,
+        ]
+        .into_iter()
+        .filter_map(|code| self.get(code).map(|value| (code, value)))
+        .collect()
+    }
+
+    /// Rewrites a message about to be sent to this peer with the identity AVPs and the
+    /// Hop-by-Hop and End-to-End Identifiers of the connection, the header has no setters.
+    ///
+    /// AVPs present in the message are replaced in place, missing ones are appended,
+    /// except Destination-* which are not allowed in CER.
+    pub fn apply(
+        &self,
+        msg: DiameterMessage,
+        (hop_by_hop, end_to_end): (u32, u32),
+        dict: Arc<Dictionary>,
+    ) -> DiameterMessage {
+        let mut result = DiameterMessage::new(
+            msg.get_command_code(),
+            msg.get_application_id(),
+            msg.get_flags(),
+            hop_by_hop,
+            end_to_end,
+            Arc::clone(&dict),
+        );
+
+        for avp in msg.get_avps() {
+            match self.get(avp.get_code()) {
+                // Flags of the template are kept
+                Some(value) if avp.get_vendor_id().is_none() => {
+                    let mut flags = 0;
+                    if avp.get_flags().mandatory {
+                        flags |= M;
+                    }
+                    if avp.get_flags().private {
+                        flags |= P;
+                    }
+                    result.add_avp(avp.get_code(), None, flags, Identity::new(value).into());
+                }
+                _ => result.add(avp.clone()),
+            }
+        }
+
+        for (code, value) in self.overrides() {
+            if msg.get_avp(code).is_some() {
+                continue;
+            }
+            if msg.get_command_code() == CommandCode::CapabilitiesExchange
+                && (code == DESTINATION_HOST || code == DESTINATION_REALM)
+            {
+                continue;
+            }
+            result.add(Avp::new(
+                code,
+                None,
+                M,
+                Identity::new(value).into(),
+                Arc::clone(&dict),
+            ));
+        }
+
+        result
+    }
+}
+
+/// Smooth weighted round-robin, as used by nginx upstreams.
+///
+/// Peers are picked in proportion to their weight while interleaving them,
+/// so weights 3:1 give `A A B A` instead of `A A A B`.
+pub struct WeightedSelector {
+    weights: Vec<i64>,
+    current: Vec<i64>,
+}
+
+impl WeightedSelector {
+    pub fn new(weights: &[u32]) -> Self {
+        let weights: Vec<i64> = weights.iter().map(|w| *w as i64).collect();
+        WeightedSelector {
+            current: vec![0; weights.len()],
+            weights,
+        }
+    }
+
+    /// Picks among the `available` peers, `None` when there is none.
+    pub fn next(&mut self, available: &[bool]) -> Option<usize> {
+        let mut best: Option<usize> = None;
+        let mut total = 0;
+        for (i, _) in available.iter().enumerate().filter(|(_, &a)| a) {
+            self.current[i] += self.weights[i];
+            total += self.weights[i];
+            if best.is_none_or(|best| self.current[i] > self.current[best]) {
+                best = Some(i);
+            }
+        }
+        let best = best?;
+        self.current[best] -= total;
+        Some(best)
+    }
+}
+
+/// Picks the connection for the next request according to the distribution policy.
+pub struct Distributor {
+    policy: Distribution,
+    weighted: WeightedSelector,
+    count: usize,
+    next: usize,
+}
+
+impl Distributor {
+    /// `weights` holds one entry per connection, the weight of the peer it belongs to.
+    pub fn new(policy: Distribution, weights: &[u32]) -> Self {
+        Distributor {
+            policy,
+            weighted: WeightedSelector::new(weights),
+            count: weights.len(),
+            next: 0,
+        }
+    }
+
+    /// `outstanding` holds the number of requests awaiting an answer on each connection,
+    /// `available` whether it may be given new requests. `None` when none may.
+    ///
+    /// Sticky distribution picks by `session_id`, in turn without one.
+    pub fn select(
+        &mut self,
+        session_id: Option<&str>,
+        outstanding: &[u32],
+        available: &[bool],
+    ) -> Option<usize> {
+        if self.count == 0 {
+            return None;
+        }
+        match self.policy {
+            Distribution::RoundRobin => self.round_robin(available),
+            Distribution::Weighted => self.weighted.next(available),
+            Distribution::LeastOutstanding => {
+                // Start scanning from a rotating offset so ties are spread evenly
+                let start = self.round_robin(available)?;
+                (0..self.count)
+                    .map(|i| (start + i) % self.count)
+                    .filter(|&i| available[i])
+                    .min_by_key(|&i| outstanding[i])
+            }
+            Distribution::Sticky => match session_id {
+                Some(session_id) => {
+                    let mut hasher = DefaultHasher::new();
+                    session_id.hash(&mut hasher);
+                    let hash = hasher.finish();
+                    let first = (hash % self.count as u64) as usize;
+                    if available[first] {
+                        return Some(first);
+                    }
+                    // Sessions of an unavailable connection are spread over the others,
+                    // the sessions of the others stay where they are
+                    let count = available.iter().filter(|&&a| a).count();
+                    if count == 0 {
+                        return None;
+                    }
+                    let n = (hash / self.count as u64 % count as u64) as usize;
+                    (0..self.count).filter(|&i| available[i]).nth(n)
+                }
+                None => self.round_robin(available),
+            },
+        }
+    }
+
+    fn round_robin(&mut self, available: &[bool]) -> Option<usize> {
+        for _ in 0..self.count {
+            let index = self.next;
+            self.next = (self.next + 1) % self.count;
+            if available[index] {
+                return Some(index);
+            }
+        }
+        None
+    }
+}
+
+pub fn session_id(msg: &DiameterMessage) -> Option<&str> {
+    match msg.get_avp(SESSION_ID)?.get_value() {
+        AvpValue::UTF8String(value) => Some(value.value()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diameter::avp::UTF8String;
+    use diameter::flags;
+    use diameter::ApplicationId;
+
+    fn ccr(session_id: &str, dict: Arc<Dictionary>) -> DiameterMessage {
+        let mut msg = DiameterMessage::new(
+            CommandCode::CreditControl,
+            ApplicationId::CreditControl,
+            flags::REQUEST,
+            1,
+            1,
+            dict,
+        );
+        msg.add_avp(SESSION_ID, None, M, UTF8String::new(session_id).into());
+        msg.add_avp(
+            ORIGIN_HOST,
+            None,
+            M,
+            Identity::new("host.example.com").into(),
+        );
+        msg
+    }
+
+    #[test]
+    fn test_weighted_selector() {
+        let mut selector = WeightedSelector::new(&[3, 1]);
+        let picks: Vec<usize> = (0..8).map(|_| selector.next(&[true; 2]).unwrap()).collect();
+        assert_eq!(picks, vec![0, 0, 1, 0, 0, 0, 1, 0]);
+        assert_eq!(selector.next(&[false, true]), Some(1));
+        assert_eq!(selector.next(&[false, true]), Some(1));
+        assert_eq!(selector.next(&[false; 2]), None);
+    }
+
+    #[test]
+    fn test_weighted_selector_skips_zero_weight() {
+        let mut selector = WeightedSelector::new(&[0, 2, 1]);
+        let mut counts = [0; 3];
+        for _ in 0..30 {
+            counts[selector.next(&[true; 3]).unwrap()] += 1;
+        }
+        assert_eq!(counts, [0, 20, 10]);
+    }
+
+    #[test]
+    fn test_identity_overrides() {
+        let identity = PeerIdentity {
+            origin_host: Some("petrel.example.com".into()),
+            destination_realm: Some("ocs.example.com".into()),
+            ..Default::default()
+        };
+        assert_eq!(
+            identity.overrides(),
+            vec![
+                (ORIGIN_HOST, "petrel.example.com"),
+                (DESTINATION_REALM, "ocs.example.com")
+            ]
+        );
+        assert_eq!(identity.get(ORIGIN_REALM), None);
+
+        let dict = Arc::new(Dictionary::new(&[&diameter::dictionary::DEFAULT_DICT_XML]));
+        let msg = identity.apply(ccr("ses;1", Arc::clone(&dict)), (7, 9), Arc::clone(&dict));
+        assert_eq!(msg.get_hop_by_hop_id(), 7);
+        assert_eq!(msg.get_end_to_end_id(), 9);
+        assert_eq!(msg.get_flags(), flags::REQUEST);
+        let avps = msg.get_avps();
+        assert_eq!(avps.len(), 3);
+        assert_eq!(avps[1].get_code(), ORIGIN_HOST);
+        assert_eq!(
+            avps[1].get_identity().unwrap().value(),
+            "petrel.example.com"
+        );
+        assert_eq!(avps[2].get_code(), DESTINATION_REALM);
+        assert_eq!(session_id(&msg), Some("ses;1"));
+
+        // Without overrides only the identifiers change
+        let msg = PeerIdentity::default().apply(msg, (8, 10), dict);
+        assert_eq!(msg.get_hop_by_hop_id(), 8);
+        assert_eq!(msg.get_end_to_end_id(), 10);
+        assert_eq!(msg.get_avps().len(), 3);
+    }
+
+    #[test]
+    fn test_distributor_round_robin() {
+        let mut distributor = Distributor::new(Distribution::RoundRobin, &[5, 1, 1]);
+        let picks: Vec<usize> = (0..4)
+            .map(|_| distributor.select(None, &[0; 3], &[true; 3]).unwrap())
+            .collect();
+        assert_eq!(picks, vec![0, 1, 2, 0]);
+
+        let available = [false, true, false];
+        assert_eq!(distributor.select(None, &[0; 3], &available), Some(1));
+        assert_eq!(distributor.select(None, &[0; 3], &[false; 3]), None);
+    }
+
+    #[test]
+    fn test_distributor_least_outstanding() {
+        let mut distributor = Distributor::new(Distribution::LeastOutstanding, &[1, 1, 1]);
+        let all = [true; 3];
+        assert_eq!(distributor.select(None, &[4, 2, 3], &all), Some(1));
+        assert_eq!(distributor.select(None, &[0, 2, 0], &all), Some(2));
+        assert_eq!(distributor.select(None, &[0, 2, 1], &all), Some(0));
+        let available = [true, false, true];
+        assert_eq!(distributor.select(None, &[4, 2, 3], &available), Some(2));
+    }
+
+    #[test]
+    fn test_distributor_sticky() {
+        let mut distributor = Distributor::new(Distribution::Sticky, &[1, 1, 1, 1]);
+        for i in 0..20 {
+            let session_id = format!("ses;{}", i);
+            let id = Some(session_id.as_str());
+            let first = distributor.select(id, &[0; 4], &[true; 4]).unwrap();
+            assert_eq!(distributor.select(id, &[0; 4], &[true; 4]), Some(first));
+            assert_eq!(
+                distributor.select(id, &[9, 9, 9, 9], &[true; 4]),
+                Some(first)
+            );
+
+            let mut available = [true; 4];
+            available[first] = false;
+            let moved = distributor.select(id, &[0; 4], &available).unwrap();
+            assert_ne!(moved, first);
+            assert_eq!(distributor.select(id, &[0; 4], &available), Some(moved));
+        }
+
+        // Sessions of the connections still available stay on them
+        let mut available = [true; 4];
+        available[2] = false;
+        let mut kept = 0;
+        for i in 0..100 {
+            let session_id = format!("ses;{}", i);
+            let id = Some(session_id.as_str());
+            let first = distributor.select(id, &[0; 4], &[true; 4]).unwrap();
+            let now = distributor.select(id, &[0; 4], &available).unwrap();
+            if first != 2 {
+                assert_eq!(now, first);
+                kept += 1;
+            }
+        }
+        assert!(kept > 0 && kept < 100);
+
+        // No connection at all
+        let mut none = Distributor::new(Distribution::Sticky, &[]);
+        assert_eq!(none.select(Some("ses;1"), &[], &[]), None);
+
+        // Without a Session-Id, in turn
+        let picks: Vec<usize> = (0..4)
+            .map(|_| distributor.select(None, &[0; 4], &[true; 4]).unwrap())
+            .collect();
+        assert_eq!(picks, vec![0, 1, 2, 3]);
+    }
+}
@@ -0,0 +1,453 @@
+//! Final summary of a run, merged across the runners and written as JSON or CSV.
+use crate::options::Options;
+use crate::runner::{PeerReport, RunReport, ScenarioReport};
+use crate::stats::{Latency, LiveLine};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use tokio::time::Duration;
+
+pub struct Summary {
+    options: Options,
+    rps: f64,
+    elapsed: Duration,
+    interrupted: bool,
+    latency: Latency,
+    peers: BTreeMap<String, PeerReport>,
+    scenarios: Vec<ScenarioReport>,
+    series: Vec<LiveLine>,
+}
+
+impl Summary {
+    pub fn new(options: Options) -> Self {
+        Summary {
+            options,
+            rps: 0.0,
+            elapsed: Duration::from_secs(0),
+            interrupted: false,
+            latency: Latency::default(),
+            peers: BTreeMap::new(),
+            scenarios: vec![],
+            series: vec![],
+        }
+    }
+
+    /// Merges the report of one runner.
+    pub fn add(&mut self, report: RunReport) {
+        self.rps += report.rps;
+        self.elapsed = self.elapsed.max(report.elapsed);
+        self.interrupted |= report.interrupted;
+        self.latency.merge(&report.latency);
+        for (i, scenario) in report.scenarios.into_iter().enumerate() {
+            match self.scenarios.get_mut(i) {
+                Some(total) => total.merge(&scenario),
+                None => self.scenarios.push(scenario),
+            }
+        }
+        for peer in report.peers {
+            match self.peers.get_mut(&peer.address) {
+                Some(total) => {
+                    total.responses += peer.responses;
+                    total.connect_failures += peer.connect_failures;
+                    total.dwr_sent += peer.dwr_sent;
+                    total.watchdog_failures += peer.watchdog_failures;
+                    total.dwr_answered += peer.dwr_answered;
+                }
+                None => {
+                    self.peers.insert(peer.address.clone(), peer);
+                }
+            }
+        }
+    }
+
+    pub fn set_series(&mut self, series: Vec<LiveLine>) {
+        self.series = series;
+    }
+
+    pub fn log(&self) {
+        if self.interrupted {
+            log::warn!("Run was interrupted, results cover a partial run");
+        }
+        log::info!("Total RPS: {}", self.rps);
+        log::info!("Elapsed: {:?}", self.elapsed);
+        log::info!("Latency: {}", self.latency);
+        for scenario in &self.scenarios {
+            log::info!(
+                "Scenario {}: {} requests, {} answers, {} timeouts, {} errors",
+                scenario.name,
+                scenario.requests,
+                scenario.answers,
+                scenario.timeouts,
+                scenario.errors
+            );
+            if scenario.assertion_failures > 0 {
+                log::warn!(
+                    "Scenario {}: {} answers failed assertions",
+                    scenario.name,
+                    scenario.assertion_failures
+                );
+                for (name, count) in &scenario.failed_assertions {
+                    log::warn!(
+                        "Scenario {} assertion '{}': {} failures",
+                        scenario.name,
+                        name,
+                        count
+                    );
+                }
+            }
+            log::info!("Scenario {} latency: {}", scenario.name, scenario.latency);
+            log::info!("Scenario {} results: {}", scenario.name, scenario.results);
+        }
+        for (address, peer) in &self.peers {
+            if peer.connect_failures > 0 {
+                log::warn!(
+                    "Peer {}: {} connection(s) failed",
+                    address,
+                    peer.connect_failures
+                );
+            }
+            log::info!(
+                "Peer {}: {} responses, watchdog: {} DWR sent, {} failures, {} DWR answered",
+                address,
+                peer.responses,
+                peer.dwr_sent,
+                peer.watchdog_failures,
+                peer.dwr_answered
+            );
+        }
+    }
+
+    /// Writes the report files set in the options, if any.
+    pub fn write(&self) {
+        if let Some(path) = &self.options.report.json {
+            let json = serde_json::to_string_pretty(&self.to_json()).unwrap();
+            match std::fs::write(path, json) {
+                Ok(_) => log::info!("JSON report written to {}", path),
+                Err(e) => log::error!("Failed to write JSON report {}: {}", path, e),
+            }
+        }
+        if let Some(path) = &self.options.report.csv {
+            match std::fs::write(path, self.to_csv()) {
+                Ok(_) => log::info!("CSV report written to {}", path),
+                Err(e) => log::error!("Failed to write CSV report {}: {}", path, e),
+            }
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        let scenarios: Vec<Value> = self
+            .scenarios
+            .iter()
+            .map(|scenario| {
+                let classes: BTreeMap<&str, u64> = scenario
+                    .results
+                    .by_class()
+                    .into_iter()
+                    .map(|(class, count)| (class.name(), count))
+                    .collect();
+                json!({
+                    "name": scenario.name,
+                    "requests": scenario.requests,
+                    "answers": scenario.answers,
+                    "timeouts": scenario.timeouts,
+                    "errors": scenario.errors,
+                    "assertion_failures": scenario.assertion_failures,
+                    "failed_assertions": scenario.failed_assertions,
+                    "unsuccessful": scenario.results.failures,
+                    "latency": latency_json(&scenario.latency),
+                    "result_classes": classes,
+                    "result_codes": scenario.results.codes,
+                    "missing_result_code": scenario.results.missing,
+                })
+            })
+            .collect();
+
+        let peers: Vec<Value> = self
+            .peers
+            .values()
+            .map(|peer| {
+                json!({
+                    "address": peer.address,
+                    "responses": peer.responses,
+                    "connect_failures": peer.connect_failures,
+                    "dwr_sent": peer.dwr_sent,
+                    "watchdog_failures": peer.watchdog_failures,
+                    "dwr_answered": peer.dwr_answered,
+                })
+            })
+            .collect();
+
+        let series: Vec<Value> = self
+            .series
+            .iter()
+            .map(|line| {
+                json!({
+                    "elapsed_s": line.elapsed.as_secs_f64(),
+                    "tps": line.tps,
+                    "outstanding": line.outstanding,
+                    "unsuccessful": line.failures,
+                    "errors": line.errors,
+                    "timeouts": line.timeouts,
+                    "latency": latency_json(&line.latency),
+                })
+            })
+            .collect();
+
+        json!({
+            "config": self.options,
+            "rps": self.rps,
+            "elapsed_s": self.elapsed.as_secs_f64(),
+            "interrupted": self.interrupted,
+            "latency": latency_json(&self.latency),
+            "scenarios": scenarios,
+            "peers": peers,
+            "series": series,
+        })
+    }
+
+    /// One `section,name,metric,value` row per figure, so results diff line by line.
+    pub fn to_csv(&self) -> String {
+        let mut rows: Vec<(&str, String, String, String)> = vec![];
+
+        if let Value::Object(config) = json!(self.options) {
+            for (key, value) in config {
+                if let Value::String(_) | Value::Number(_) | Value::Bool(_) = value {
+                    let value = value.as_str().map_or(value.to_string(), str::to_string);
+                    rows.push(("config", String::new(), key, value));
+                }
+            }
+        }
+
+        rows.push(("total", String::new(), "rps".into(), self.rps.to_string()));
+        let elapsed = self.elapsed.as_secs_f64().to_string();
+        rows.push(("total", String::new(), "elapsed_s".into(), elapsed));
+        let interrupted = self.interrupted.to_string();
+        rows.push(("total", String::new(), "interrupted".into(), interrupted));
+        for (metric, value) in latency_metrics(&self.latency) {
+            rows.push(("total", String::new(), metric.into(), value.to_string()));
+        }
+
+        for scenario in &self.scenarios {
+            let name = &scenario.name;
+            let counts = [
+                ("requests", scenario.requests),
+                ("answers", scenario.answers),
+                ("timeouts", scenario.timeouts),
+                ("errors", scenario.errors),
+                ("assertion_failures", scenario.assertion_failures),
+                ("unsuccessful", scenario.results.failures),
+                ("missing_result_code", scenario.results.missing),
+            ];
+            for (metric, value) in counts.into_iter().chain(latency_metrics(&scenario.latency)) {
+                rows.push(("scenario", name.clone(), metric.into(), value.to_string()));
+            }
+            for (class, count) in scenario.results.by_class() {
+                let metric = format!("result_class_{}", class.name());
+                rows.push(("scenario", name.clone(), metric, count.to_string()));
+            }
+            for (code, count) in &scenario.results.codes {
+                let metric = format!("result_code_{}", code);
+                rows.push(("scenario", name.clone(), metric, count.to_string()));
+            }
+            for (assertion, count) in &scenario.failed_assertions {
+                let metric = format!("assertion: {}", assertion);
+                rows.push(("scenario", name.clone(), metric, count.to_string()));
+            }
+        }
+
+        for peer in self.peers.values() {
+            let counts = [
+                ("responses", peer.responses),
+                ("connect_failures", peer.connect_failures),
+                ("dwr_sent", peer.dwr_sent),
+                ("watchdog_failures", peer.watchdog_failures),
+                ("dwr_answered", peer.dwr_answered),
+            ];
+            for (metric, value) in counts {
+                rows.push((
+                    "peer",
+                    peer.address.clone(),
+                    metric.into(),
+                    value.to_string(),
+                ));
+            }
+        }
+
+        for line in &self.series {
+            let name = format!("{:.3}", line.elapsed.as_secs_f64());
+            rows.push(("series", name.clone(), "tps".into(), line.tps.to_string()));
+            let counts = [
+                ("outstanding", line.outstanding),
+                ("unsuccessful", line.failures),
+                ("errors", line.errors),
+                ("timeouts", line.timeouts),
+            ];
+            for (metric, value) in counts.into_iter().chain(latency_metrics(&line.latency)) {
+                rows.push(("series", name.clone(), metric.into(), value.to_string()));
+            }
+        }
+
+        let mut csv = String::from("section,name,metric,value\n");
+        for (section, name, metric, value) in rows {
+            writeln!(
+                csv,
+                "{},{},{},{}",
+                section,
+                csv_field(&name),
+                csv_field(&metric),
+                csv_field(&value)
+            )
+            .unwrap();
+        }
+        csv
+    }
+}
+
+fn latency_metrics(latency: &Latency) -> Vec<(&'static str, u64)> {
+    if latency.count() == 0 {
+        return vec![("latency_count", 0)];
+    }
+    vec![
+        ("latency_count", latency.count()),
+        ("latency_min_us", latency.min().as_micros() as u64),
+        ("latency_mean_us", latency.mean().as_micros() as u64),
+        (
+            "latency_p50_us",
+            latency.percentile(50.0).as_micros() as u64,
+        ),
+        (
+            "latency_p90_us",
+            latency.percentile(90.0).as_micros() as u64,
+        ),
+        (
+            "latency_p99_us",
+            latency.percentile(99.0).as_micros() as u64,
+        ),
+        (
+            "latency_p99.9_us",
+            latency.percentile(99.9).as_micros() as u64,
+        ),
+        ("latency_max_us", latency.max().as_micros() as u64),
+    ]
+}
+
+fn latency_json(latency: &Latency) -> Value {
+    let metrics: serde_json::Map<String, Value> = latency_metrics(latency)
+        .into_iter()
+        .map(|(metric, value)| {
+            let key = metric.trim_start_matches("latency_").to_string();
+            (key, value.into())
+        })
+        .collect();
+    Value::Object(metrics)
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options;
+
+    fn options() -> Options {
+        options::for_test(r#"parallel = 2, report = { json = "report.json" }"#)
+    }
+
+    fn run_report(address: &str, latency_ms: u64, code: u32) -> RunReport {
+        let mut scenario = ScenarioReport {
+            name: "CCR, initial".into(),
+            requests: 2,
+            answers: 1,
+            timeouts: 1,
+            ..Default::default()
+        };
+        if code != 2001 {
+            scenario.assertion_failures = 1;
+            let name = "Result-Code >= 2000 and <= 2999";
+            scenario.failed_assertions.insert(name.into(), 1);
+        }
+        scenario.latency.record(Duration::from_millis(latency_ms));
+        scenario.results.record(Some(code), code == 2001);
+        let latency = scenario.latency.clone();
+        RunReport {
+            rps: 10.0,
+            elapsed: Duration::from_millis(latency_ms * 1000),
+            interrupted: false,
+            latency,
+            peers: vec![PeerReport {
+                address: address.into(),
+                responses: 1,
+                connect_failures: 0,
+                dwr_sent: 2,
+                watchdog_failures: 0,
+                dwr_answered: 1,
+            }],
+            scenarios: vec![scenario],
+        }
+    }
+
+    #[test]
+    fn test_summary_json() {
+        let mut summary = Summary::new(options());
+        summary.add(run_report("localhost:3868", 2, 2001));
+        summary.add(run_report("localhost:3868", 3, 5030));
+
+        let json = summary.to_json();
+        assert_eq!(json["config"]["call_timeout"], "1s");
+        assert_eq!(json["config"]["report"]["json"], "report.json");
+        assert_eq!(json["rps"], 20.0);
+        assert_eq!(json["elapsed_s"], 3.0);
+        assert_eq!(json["latency"]["count"], 2);
+
+        let scenario = &json["scenarios"][0];
+        assert_eq!(scenario["requests"], 4);
+        assert_eq!(scenario["timeouts"], 2);
+        assert_eq!(scenario["unsuccessful"], 1);
+        assert_eq!(scenario["result_classes"]["2xxx"], 1);
+        assert_eq!(scenario["result_codes"]["5030"], 1);
+        assert_eq!(scenario["assertion_failures"], 1);
+        assert_eq!(
+            scenario["failed_assertions"]["Result-Code >= 2000 and <= 2999"],
+            1
+        );
+        assert_eq!(scenario["latency"]["min_us"], 2000);
+
+        assert_eq!(json["peers"][0]["dwr_sent"], 4);
+        assert_eq!(json["series"], json!([]));
+    }
+
+    #[test]
+    fn test_summary_csv() {
+        let mut summary = Summary::new(options());
+        summary.add(run_report("localhost:3868", 2, 2001));
+
+        let csv = summary.to_csv();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], "section,name,metric,value");
+        assert!(lines.contains(&"config,,target_rps,100"));
+        assert!(lines.contains(&"config,,call_timeout,1s"));
+        assert!(lines.contains(&"total,,rps,10"));
+        assert!(lines.contains(&"scenario,\"CCR, initial\",requests,2"));
+        assert!(lines.contains(&"scenario,\"CCR, initial\",result_code_2001,1"));
+        assert!(lines.contains(&"scenario,\"CCR, initial\",latency_p50_us,2000"));
+        assert!(lines.contains(&"peer,localhost:3868,dwr_answered,1"));
+        assert!(lines.contains(&"scenario,\"CCR, initial\",assertion_failures,0"));
+
+        summary.add(run_report("localhost:3868", 3, 5030));
+        let csv = summary.to_csv();
+        assert!(csv.contains("\"CCR, initial\",assertion: Result-Code >= 2000 and <= 2999,1\n"));
+        assert_eq!(
+            summary.options.report,
+            options::Report {
+                json: Some("report.json".into()),
+                csv: None
+            }
+        );
+    }
+}
@@ -1,16 +1,24 @@
-use crate::dictionary;
-use crate::global::Global;
+use crate::dictionary::EnumDictionary;
+use crate::global::{Global, Shared};
+use crate::identifiers::Identifiers;
 use crate::options;
+use crate::options::Distribution;
 use crate::options::Options;
 use crate::options::ScenarioType;
-use crate::scenario;
-use diameter::transport::DiameterClient;
-use diameter::transport::DiameterClientConfig;
+use crate::peer::{self, Distributor, PeerIdentity};
+use crate::scenario::{self, Session};
+use crate::stats::{self, Latency, LiveStats, ResultCodes};
+use crate::transport::{Connection, Origin};
+use crate::watchdog::{Watchdog, WatchdogReport};
+use diameter::dictionary::Dictionary;
 use diameter::DiameterMessage;
+use std::collections::BTreeMap;
 use std::sync::Arc;
 use std::time::Instant;
 use tokio::sync::mpsc::channel;
 use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::oneshot;
+use tokio::sync::watch;
 use tokio::task;
 use tokio::task::LocalSet;
 use tokio::time::{self, Duration};
@@ -22,26 +30,27 @@ pub struct RunParameter {
     pub interval: Duration,
     pub total_iterations: u32,
     pub total_requests: u32,
-    pub scenario_count: usize,
+    pub session_length: usize,
 }
 
 impl RunParameter {
     pub fn new(options: &Options) -> RunParameter {
-        // count scenario with type == Repeating
-        let scenario_count = options
+        // Messages of a session, each Repeating scenario is sent `times` in a row
+        let session_length: u32 = options
             .scenarios
             .iter()
             .filter(|s| s.scenario_type == ScenarioType::Repeating)
-            .count();
+            .map(|s| s.times)
+            .sum();
 
-        let scenario_count = if scenario_count == 0 {
+        let session_length = if session_length == 0 {
             1
         } else {
-            scenario_count
+            session_length as usize
         };
 
         let target_rps = options.target_rps;
-        let target_tps = target_rps / scenario_count as u32;
+        let target_tps = target_rps / session_length as u32;
         let target_tps = if target_tps == 0 { 1 } else { target_tps };
 
         let batch_size = match options.batch_size {
@@ -57,7 +66,7 @@ impl RunParameter {
 
         let total_requests = target_rps * duration_s;
 
-        let total_iterations = total_requests as f64 / batch_size as f64 / scenario_count as f64;
+        let total_iterations = total_requests as f64 / batch_size as f64 / session_length as f64;
         let total_iterations = total_iterations.ceil() as u32;
 
         RunParameter {
@@ -66,7 +75,7 @@ impl RunParameter {
             interval,
             total_requests,
             total_iterations,
-            scenario_count,
+            session_length,
         }
     }
 }
@@ -74,117 +83,366 @@ impl RunParameter {
 pub struct RunReport {
     pub rps: f64,
     pub elapsed: Duration,
+    pub interrupted: bool,
+    pub latency: Latency,
+    pub peers: Vec<PeerReport>,
+    pub scenarios: Vec<ScenarioReport>,
 }
 
-pub async fn run(options: Options, param: RunParameter) -> RunReport {
-    let global = Global::new(&options.globals);
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ScenarioReport {
+    pub name: String,
+    pub requests: u64,
+    pub answers: u64,
+    pub timeouts: u64,
+    pub errors: u64,
+    /// Answers failing at least one assertion
+    pub assertion_failures: u64,
+    /// Failures by assertion name
+    pub failed_assertions: BTreeMap<String, u64>,
+    pub latency: Latency,
+    pub results: ResultCodes,
+}
+
+impl ScenarioReport {
+    pub fn merge(&mut self, other: &ScenarioReport) {
+        self.requests += other.requests;
+        self.answers += other.answers;
+        self.timeouts += other.timeouts;
+        self.errors += other.errors;
+        self.assertion_failures += other.assertion_failures;
+        for (name, count) in &other.failed_assertions {
+            *self.failed_assertions.entry(name.clone()).or_default() += count;
+        }
+        self.latency.merge(&other.latency);
+        self.results.merge(&other.results);
+    }
+}
+
+pub struct PeerReport {
+    pub address: String,
+    pub responses: u64,
+    /// Connections which could not be opened, or got no CEA
+    pub connect_failures: u64,
+    pub dwr_sent: u64,
+    pub watchdog_failures: u64,
+    pub dwr_answered: u64,
+}
 
-    let dict = dictionary::load(options.dictionaries.clone()).unwrap();
-    let dict = Arc::new(dict);
+struct Peer {
+    address: String,
+    identity: PeerIdentity,
+    responses: u64,
+    connect_failures: u64,
+    watchdogs: Vec<WatchdogReport>,
+}
+
+/// Runs the scenarios until done, or until `shutdown` is set to true.
+///
+/// `options` must have been validated against `dict` and `enums`, progress is published to `live`.
+/// Variables shared with the other runners take their values from `shared`.
+pub async fn run(
+    options: Options,
+    param: RunParameter,
+    dict: Arc<Dictionary>,
+    enums: Arc<EnumDictionary>,
+    shared: Shared,
+    live: Arc<LiveStats>,
+    mut shutdown: watch::Receiver<bool>,
+) -> RunReport {
+    let global = Global::new_shared(&options.globals, &shared)
+        .unwrap()
+        .with_captures(&options.scenarios);
 
     // TODO - remove hardcode
     let mut init_scenario = scenario::Scenario::new(
-        options.scenarios.get(0).unwrap(),
+        options.scenarios.first().unwrap(),
         &global,
         Arc::clone(&dict),
+        &enums,
     )
     .unwrap();
 
     // Skip first scenario, which is hardcoded as Init scenario for now
     let mut repeating_scenarios = vec![];
     for scenario in options.scenarios.iter().skip(1) {
-        let s = scenario::Scenario::new(scenario, &global, Arc::clone(&dict)).unwrap();
+        let s = scenario::Scenario::new(scenario, &global, Arc::clone(&dict), &enums).unwrap();
         repeating_scenarios.push(s);
     }
 
+    // Call flow walked by each session, as indexes of the repeating scenarios
+    let steps: Vec<usize> = options
+        .scenarios
+        .iter()
+        .skip(1)
+        .enumerate()
+        .flat_map(|(scenario_id, s)| std::iter::repeat_n(scenario_id, s.times as usize))
+        .collect();
+
     let local = LocalSet::new();
     local
         .run_until(async move {
-            // Connect to server
-            let config = DiameterClientConfig {
-                use_tls: false,
-                verify_cert: false,
-            };
-            let mut client = DiameterClient::new("localhost:3868", config);
-            let mut handler = client.connect().await.unwrap();
-            let dict_ref = Arc::clone(&dict);
-            task::spawn_local(async move {
-                DiameterClient::handle(&mut handler, dict_ref).await;
-            });
+            // Connect to peers, each peer may have several connections
+            let mut peers = vec![];
+            let mut clients = vec![];
+            let mut connection_peer = vec![];
+            let mut weights = vec![];
+            let mut watchdogs = vec![];
+            let mut connections = vec![];
+            // Values captured from the CEA of each connection
+            let mut init_captures = vec![];
+            for (peer_id, peer_options) in options.peers.iter().enumerate() {
+                let mut peer = Peer {
+                    address: peer_options.address(),
+                    identity: PeerIdentity::new(peer_options),
+                    responses: 0,
+                    connect_failures: 0,
+                    watchdogs: vec![],
+                };
+
+                for _ in 0..peer_options.connections {
+                    // A stream of identifiers for each connection of each runner
+                    let stream = (shared.runner() as u64) << 32 | clients.len() as u64;
+                    let identifiers = Identifiers::new(options.identifiers.seed, stream);
+                    let opened = open_connection(
+                        &peer,
+                        peer_options,
+                        &options,
+                        identifiers,
+                        &mut init_scenario,
+                        &dict,
+                    )
+                    .await;
+                    // The run goes on with the other connections, sessions find none
+                    // available when all failed
+                    let (client, origin, captured) = match opened {
+                        Ok(opened) => opened,
+                        Err(e) => {
+                            log::error!("Connection to {} failed: {}", peer.address, e);
+                            peer.connect_failures += 1;
+                            continue;
+                        }
+                    };
+                    init_captures.push(Arc::new(captured));
+
+                    if options.watchdog.enable {
+                        let watchdog = Watchdog::new(
+                            &peer.address,
+                            client.clone(),
+                            origin.clone(),
+                            options.watchdog.interval,
+                            Arc::clone(&dict),
+                        );
+                        let (stop_tx, stop_rx) = oneshot::channel();
+                        let handle = task::spawn_local(watchdog.run(stop_rx));
+                        watchdogs.push((peer_id, stop_tx, handle));
+                    }
 
-            // Init scenario, send CER
-            let cer = init_scenario.next_message().unwrap();
-            if options.log_requests {
-                log::info!("CER: {}", cer);
-            }
-            let resp = client.send_message(cer).await.unwrap();
-            let cea = resp.await.unwrap();
-            if options.log_responses {
-                log::info!("CEA: {}", cea);
+                    connections.push((client.clone(), origin.clone(), peer_id));
+                    clients.push(client);
+                    connection_peer.push(peer_id);
+                    weights.push(peer_options.weight);
+                }
+
+                log::info!(
+                    "Connected to peer {} with {} connection(s)",
+                    peer.address,
+                    peer_options.connections - peer.connect_failures as u32
+                );
+                peers.push(peer);
             }
+            let mut distributor = Distributor::new(options.distribution, &weights);
+            let mut outstanding = vec![0u32; clients.len()];
+            let mut available = vec![true; clients.len()];
+            let mut scenario_reports: Vec<ScenarioReport> = repeating_scenarios
+                .iter()
+                .map(|scenario| ScenarioReport {
+                    name: scenario.get_name().into(),
+                    ..Default::default()
+                })
+                .collect();
 
             // Event Loop
             let (eventloop_tx, eventloop_rx) = channel(32);
+            let call_timeout = options.call_timeout;
             tokio::spawn(async move {
-                event_loop(client, eventloop_rx).await.unwrap();
+                event_loop(clients, call_timeout, eventloop_rx)
+                    .await
+                    .unwrap();
             });
 
             // Start Repeating Scenario
             //
             log::info!(
-                "Sending total requests {}, iteraations {}, with {} TPS, batch size {}, interval {}",
+                "Sending total requests {}, iteraations {}, with {} TPS, batch size {}, \
+                 {} message(s) per session, interval {}",
                 param.total_requests,
                 param.total_iterations,
                 param.target_tps,
                 param.batch_size,
+                param.session_length,
                 param.interval.as_secs_f64()
             );
 
             let start = Instant::now();
+            let mut requests_sent = 0u64;
+            let mut interrupted = false;
+            let (resp_tx, mut resp_rx) = channel(32);
 
             // Runner loop
             let mut interval = time::interval(param.interval);
-            for _ in 0..param.total_iterations {
-                interval.tick().await;
+            'runner: for _ in 0..param.total_iterations {
+                tokio::select! {
+                    _ = interval.tick() => {}
+                    _ = shutdown_requested(&mut shutdown) => {
+                        interrupted = true;
+                        break 'runner;
+                    }
+                }
 
-                let mut scenario_id = 0;
-                let (resp_tx, mut resp_rx) = channel(32);
+                // Connections of peers down, as seen by their watchdog, get no new requests
+                for (i, (connection, _, _)) in connections.iter().enumerate() {
+                    available[i] = connection.is_available();
+                }
+
+                let mut pending = param.batch_size as usize * steps.len();
 
                 for _ in 0..param.batch_size {
+                    // New session, starting at the first step of the call flow. The
+                    // connection is chosen first, for the message to use the values
+                    // captured from its CEA.
+                    let scenario_id = steps[0];
+                    let Some(mut connection_id) =
+                        distributor.select(None, &outstanding, &available)
+                    else {
+                        let report = &mut scenario_reports[scenario_id];
+                        message_failed(report, &live, "No peer connection available".into());
+                        pending -= steps.len();
+                        continue;
+                    };
+                    let mut session = Box::new(Session::default());
+                    session.set_init(Arc::clone(&init_captures[connection_id]));
                     let first_scenario = repeating_scenarios.get_mut(scenario_id).unwrap();
-                    let request = first_scenario.next_message().unwrap();
+                    let request = match first_scenario.next_message(&mut session) {
+                        Ok(request) => request,
+                        Err(e) => {
+                            message_failed(&mut scenario_reports[scenario_id], &live, e);
+                            pending -= steps.len();
+                            continue;
+                        }
+                    };
+                    // Sticky sessions go by their Session-Id, known once the message is
+                    // built, unless it used values captured from the CEA
+                    if options.distribution == Distribution::Sticky && !session.used_init() {
+                        let session_id = peer::session_id(&request);
+                        if let Some(id) = distributor.select(session_id, &outstanding, &available) {
+                            connection_id = id;
+                            session.set_init(Arc::clone(&init_captures[connection_id]));
+                        }
+                    }
+                    let peer = &peers[connection_peer[connection_id]];
+                    let identifiers = connections[connection_id].0.next_identifiers().await;
+                    let request = peer.identity.apply(request, identifiers, Arc::clone(&dict));
+                    outstanding[connection_id] += 1;
+                    requests_sent += 1;
+                    scenario_reports[scenario_id].requests += 1;
+                    live.request_sent();
 
                     log::debug!("Scenario: {}", first_scenario.get_name());
                     if options.log_requests {
-                        log::info!("Request : {}", request);
+                        log::info!("Request to {}: {}", peer.address, request);
                     }
 
-                    let ctx = EventContext { scenario_id };
+                    let ctx = EventContext {
+                        step: 0,
+                        scenario_id,
+                        connection_id,
+                        session,
+                    };
                     eventloop_tx
                         .send(Event::SendMessage(ctx, request, resp_tx.clone()))
                         .await
                         .unwrap();
                 }
 
-                let scenario_count = repeating_scenarios.len() as u32;
-                let total_response = param.batch_size * scenario_count;
-
-                for _ in 0..total_response {
-                    if let Some((ctx, response)) = resp_rx.recv().await {
-                        if options.log_responses {
-                            log::info!("Response : {}", response);
+                while pending > 0 {
+                    let received = tokio::select! {
+                        received = resp_rx.recv() => received,
+                        _ = shutdown_requested(&mut shutdown) => {
+                            interrupted = true;
+                            break 'runner;
+                        }
+                    };
+                    if let Some((ctx, answer)) = received {
+                        pending -= 1;
+                        outstanding[ctx.connection_id] -= 1;
+                        let peer = &mut peers[connection_peer[ctx.connection_id]];
+                        let report = &mut scenario_reports[ctx.scenario_id];
+                        let step = ctx.step + 1;
+                        let mut session = ctx.session;
+
+                        if let Answer::Received(response, _) = &answer {
+                            let scenario = &repeating_scenarios[ctx.scenario_id];
+                            scenario.capture(response, &mut session);
+                            check_assertions(scenario, response, report, &options);
+                        }
+                        if !record_answer(answer, peer, report, &live, &options) {
+                            // The rest of the session is skipped, its answers are not expected
+                            pending -= steps.len().saturating_sub(step).min(pending);
+                            continue;
                         }
-                        scenario_id = ctx.scenario_id;
 
-                        if let Some(scenario) = repeating_scenarios.get_mut(scenario_id) {
-                            let request = scenario.next_message().unwrap();
+                        if let Some(&scenario_id) = steps.get(step) {
+                            // Subsequent scenarios stay on the connection of the session
+                            // while it is available
+                            let connection_id = if connections[ctx.connection_id].0.is_available() {
+                                Some(ctx.connection_id)
+                            } else {
+                                distributor.select(None, &outstanding, &available)
+                            };
+                            let Some(connection_id) = connection_id else {
+                                let report = &mut scenario_reports[scenario_id];
+                                message_failed(
+                                    report,
+                                    &live,
+                                    "No peer connection available".into(),
+                                );
+                                pending -= (steps.len() - step).min(pending);
+                                continue;
+                            };
+                            if connection_id != ctx.connection_id {
+                                session.set_init(Arc::clone(&init_captures[connection_id]));
+                            }
+
+                            let scenario = &mut repeating_scenarios[scenario_id];
+                            let request = match scenario.next_message(&mut session) {
+                                Ok(request) => request,
+                                Err(e) => {
+                                    message_failed(&mut scenario_reports[scenario_id], &live, e);
+                                    pending -= (steps.len() - step).min(pending);
+                                    continue;
+                                }
+                            };
+                            let peer = &peers[connection_peer[connection_id]];
+                            let identifiers = connections[connection_id].0.next_identifiers().await;
+                            let request =
+                                peer.identity.apply(request, identifiers, Arc::clone(&dict));
+                            outstanding[connection_id] += 1;
+                            requests_sent += 1;
+                            scenario_reports[scenario_id].requests += 1;
+                            live.request_sent();
 
                             log::debug!("Scenario: {}", scenario.get_name());
                             if options.log_requests {
-                                log::info!("Request : {}", request);
+                                log::info!("Request to {}: {}", peer.address, request);
                             }
 
-                            let ctx = EventContext { scenario_id };
+                            let ctx = EventContext {
+                                step,
+                                scenario_id,
+                                connection_id,
+                                session,
+                            };
                             eventloop_tx
                                 .send(Event::SendMessage(ctx, request, resp_tx.clone()))
                                 .await
@@ -194,53 +452,229 @@ pub async fn run(options: Options, param: RunParameter) -> RunReport {
                 }
             }
 
+            if interrupted {
+                log::warn!("Run interrupted, shutting down");
+            }
+
+            let elapsed = start.elapsed();
+
+            // Drain outstanding requests, without starting subsequent scenarios
+            let drain_deadline = time::Instant::now() + options.call_timeout;
+            while outstanding.iter().sum::<u32>() > 0 {
+                match time::timeout_at(drain_deadline, resp_rx.recv()).await {
+                    Ok(Some((ctx, answer))) => {
+                        outstanding[ctx.connection_id] -= 1;
+                        let peer = &mut peers[connection_peer[ctx.connection_id]];
+                        let report = &mut scenario_reports[ctx.scenario_id];
+                        if let Answer::Received(response, _) = &answer {
+                            let scenario = &repeating_scenarios[ctx.scenario_id];
+                            check_assertions(scenario, response, report, &options);
+                        }
+                        record_answer(answer, peer, report, &live, &options);
+                    }
+                    _ => {
+                        log::warn!(
+                            "{} request(s) still outstanding after {:?}",
+                            outstanding.iter().sum::<u32>(),
+                            options.call_timeout
+                        );
+                        break;
+                    }
+                }
+            }
+
+            for (peer_id, stop_tx, handle) in watchdogs {
+                let _ = stop_tx.send(());
+                if let Ok(report) = handle.await {
+                    peers[peer_id].watchdogs.push(report);
+                }
+            }
+
+            // Disconnect-Peer on every connection
+            let cause = options.disconnect_cause;
+            let disconnects = connections.iter().map(|(connection, origin, peer_id)| {
+                let address = &peers[*peer_id].address;
+                let dict = Arc::clone(&dict);
+                async move {
+                    // Closing, closed by the peer, or by the watchdog once DOWN
+                    if !connection.is_available() {
+                        log::debug!("{} already disconnected", address);
+                        return;
+                    }
+                    let result = match connection.disconnect_peer(origin, cause, dict).await {
+                        Ok(response) => time::timeout(options.call_timeout, response).await,
+                        Err(e) => Ok(Err(e)),
+                    };
+                    match result {
+                        Ok(Ok(dpa)) => log::debug!("DPA from {}: {}", address, dpa),
+                        Ok(Err(e)) => log::warn!("DPR to {} failed: {}", address, e),
+                        Err(_) => {
+                            log::warn!("No DPA from {} within {:?}", address, options.call_timeout)
+                        }
+                    }
+                    connection.close().await;
+                }
+            });
+            futures::future::join_all(disconnects).await;
+
             // Terminate the event loop
             eventloop_tx.send(Event::Terminate).await.unwrap();
 
-            // sleep 1
-            // sleep(Duration::from_secs(1)).await;
-
-            let elapsed = start.elapsed();
             let elapsed_s = elapsed.as_secs() as f64 + elapsed.subsec_millis() as f64 / 1000.0;
-            let total_requests = param.total_requests as f64;
-            let rps = total_requests as f64 / (elapsed.as_micros() as f64 / 1_000_000.0);
+            let rps = requests_sent as f64 / (elapsed.as_micros() as f64 / 1_000_000.0);
             log::info!("Elapsed: {:.3}s , {} requests per second", elapsed_s, rps,);
 
-            RunReport { rps, elapsed }
+            let timeouts: u64 = scenario_reports.iter().map(|s| s.timeouts).sum();
+            if timeouts > 0 {
+                log::warn!("{} request(s) timed out after {:?}", timeouts, call_timeout);
+            }
+
+            let peers = peers
+                .into_iter()
+                .map(|peer| PeerReport {
+                    address: peer.address,
+                    responses: peer.responses,
+                    connect_failures: peer.connect_failures,
+                    dwr_sent: peer.watchdogs.iter().map(|w| w.dwr_sent).sum(),
+                    watchdog_failures: peer.watchdogs.iter().map(|w| w.failures).sum(),
+                    dwr_answered: peer.watchdogs.iter().map(|w| w.dwr_answered).sum(),
+                })
+                .collect();
+
+            let mut latency = Latency::default();
+            for scenario in &scenario_reports {
+                latency.merge(&scenario.latency);
+            }
+
+            RunReport {
+                rps,
+                elapsed,
+                interrupted,
+                latency,
+                peers,
+                scenarios: scenario_reports,
+            }
         })
         .await
 }
 
+/// Connects to `peer` and exchanges CER and CEA, returns the connection with the values
+/// captured from the CEA.
+async fn open_connection(
+    peer: &Peer,
+    peer_options: &options::Peer,
+    options: &Options,
+    identifiers: Identifiers,
+    init_scenario: &mut scenario::Scenario<'_>,
+    dict: &Arc<Dictionary>,
+) -> Result<(Connection, Origin, scenario::Captured), String> {
+    let (client, mut handler) = Connection::connect(peer_options, &options.tls, identifiers)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    // Init scenario, send CER
+    let cer = init_scenario
+        .next_message(&mut Session::default())
+        .map_err(|e| format!("CER not built: {}", e))?;
+    let identifiers = client.next_identifiers().await;
+    let cer = peer.identity.apply(cer, identifiers, Arc::clone(dict));
+    let Some(origin) = Origin::from_message(&cer) else {
+        client.close().await;
+        return Err("CER must contain Origin-Host and Origin-Realm".into());
+    };
+
+    let dict_ref = Arc::clone(dict);
+    let handler_origin = origin.clone();
+    task::spawn_local(async move {
+        Connection::handle(&mut handler, dict_ref, handler_origin).await;
+    });
+
+    if options.log_requests {
+        log::info!("CER to {}: {}", peer.address, cer);
+    }
+    let cea = match client.send_message(cer).await {
+        Ok(resp) => match time::timeout(options.call_timeout, resp).await {
+            Ok(Ok(cea)) => Ok(cea),
+            Ok(Err(e)) => Err(format!("no CEA: {}", e)),
+            Err(_) => Err(format!("no CEA within {:?}", options.call_timeout)),
+        },
+        Err(e) => Err(format!("CER not sent: {}", e)),
+    };
+    let cea = match cea {
+        Ok(cea) => cea,
+        Err(e) => {
+            client.close().await;
+            return Err(e);
+        }
+    };
+    if options.log_responses {
+        log::info!("CEA from {}: {}", peer.address, cea);
+    }
+    for (name, reason) in init_scenario.check(&cea) {
+        log::warn!(
+            "CEA from {} failed assertion '{}': {}",
+            peer.address,
+            name,
+            reason
+        );
+    }
+    let mut init_session = Session::default();
+    init_scenario.capture(&cea, &mut init_session);
+    Ok((client, origin, init_session.into_captured()))
+}
+
 struct EventContext {
+    step: usize,
     scenario_id: usize,
+    connection_id: usize,
+    session: Box<Session>,
+}
+
+/// Outcome of a request sent by the event loop.
+enum Answer {
+    Received(DiameterMessage, Duration),
+    TimedOut,
+    Failed(diameter::Error),
 }
 
 enum Event {
     SendMessage(
         EventContext,
         DiameterMessage,
-        Sender<(EventContext, DiameterMessage)>,
+        Sender<(EventContext, Answer)>,
     ),
     Terminate,
 }
 
 async fn event_loop(
-    mut client: DiameterClient,
+    clients: Vec<Connection>,
+    call_timeout: Duration,
     mut rx: Receiver<Event>,
 ) -> Result<(), Box<dyn std::error::Error>> {
     while let Some(event) = rx.recv().await {
         match event {
             Event::SendMessage(ctx, request, tx) => {
                 // send message
-                let resp = client.send_message(request).await.unwrap();
+                let client = clients[ctx.connection_id].clone();
+                let hop_by_hop = request.get_hop_by_hop_id();
+                let sent = Instant::now();
+                let resp = client.send_message(request).await;
                 tokio::spawn(async move {
-                    let response = resp.await.unwrap();
+                    let answer = match resp {
+                        Ok(resp) => match time::timeout(call_timeout, resp).await {
+                            Ok(Ok(response)) => Answer::Received(response, sent.elapsed()),
+                            Ok(Err(e)) => Answer::Failed(e),
+                            Err(_) => {
+                                // Release the slot, a late answer is dropped
+                                client.abandon(hop_by_hop).await;
+                                Answer::TimedOut
+                            }
+                        },
+                        Err(e) => Answer::Failed(e),
+                    };
 
                     // Send response back to main runner loop
-                    let scenario_id = ctx.scenario_id + 1;
-                    tx.send((EventContext { scenario_id }, response))
-                        .await
-                        .unwrap();
+                    let _ = tx.send((ctx, answer)).await;
                 });
             }
             Event::Terminate => {
@@ -249,30 +683,130 @@ async fn event_loop(
             }
         }
     }
-    todo!()
+    Ok(())
+}
+
+/// Counts an answer against its peer and scenario, returns whether it was received.
+fn record_answer(
+    answer: Answer,
+    peer: &mut Peer,
+    report: &mut ScenarioReport,
+    live: &LiveStats,
+    options: &Options,
+) -> bool {
+    match answer {
+        Answer::Received(response, latency) => {
+            peer.responses += 1;
+            report.answers += 1;
+            report.latency.record(latency);
+            let code = stats::result_code(&response);
+            let success = stats::is_success(code, &options.success_codes);
+            report.results.record(code, success);
+            live.answered(latency, success);
+            if options.log_responses {
+                log::info!("Response from {}: {}", peer.address, response);
+            }
+            true
+        }
+        Answer::TimedOut => {
+            report.timeouts += 1;
+            live.timed_out();
+            log::debug!("Request to {} timed out in {}", peer.address, report.name);
+            false
+        }
+        Answer::Failed(e) => {
+            report.errors += 1;
+            live.failed();
+            log::warn!(
+                "Request to {} failed in {}: {}",
+                peer.address,
+                report.name,
+                e
+            );
+            false
+        }
+    }
+}
+
+/// Counts the assertions failed by an answer, the session goes on regardless.
+fn check_assertions(
+    scenario: &scenario::Scenario,
+    answer: &DiameterMessage,
+    report: &mut ScenarioReport,
+    options: &Options,
+) {
+    let failed = scenario.check(answer);
+    if failed.is_empty() {
+        return;
+    }
+    report.assertion_failures += 1;
+    for (name, reason) in &failed {
+        *report
+            .failed_assertions
+            .entry(name.to_string())
+            .or_default() += 1;
+        if options.log_failed_assertions {
+            log::warn!("Assertion '{}' failed in {}: {}", name, report.name, reason);
+        }
+    }
+    if options.log_failed_assertions {
+        log::warn!("Failing answer in {}: {}", report.name, answer);
+    }
+}
+
+/// Counts a message which could not be built, which ends its session.
+fn message_failed(report: &mut ScenarioReport, live: &LiveStats, e: Box<dyn std::error::Error>) {
+    report.errors += 1;
+    live.not_sent();
+    log::warn!("Failed to build message in {}: {}", report.name, e);
+}
+
+/// Resolves once a shutdown is requested, never if the sender is gone.
+async fn shutdown_requested(shutdown: &mut watch::Receiver<bool>) {
+    if shutdown.wait_for(|stop| *stop).await.is_err() {
+        std::future::pending::<()>().await;
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::options;
+    use diameter::avp::flags::M;
+    use diameter::avp::Unsigned32;
+    use diameter::dictionary::Dictionary;
+    use diameter::{ApplicationId, CommandCode};
 
-    #[test]
-    fn test_load_calculate() {
-        let options = Options {
+    fn options() -> Options {
+        Options {
             log_level: options::LogLevel::Info,
             parallel: 1,
             target_rps: 500,
             batch_size: options::BatchSize::Auto("Auto".to_string()),
             call_timeout: Duration::from_millis(2000),
+            success_codes: vec![],
             duration: Duration::from_secs(120),
             log_requests: false,
             log_responses: false,
+            log_failed_assertions: false,
             protocol: options::Protocol::Diameter,
+            peers: vec![],
+            distribution: options::Distribution::Weighted,
+            tls: options::Tls::default(),
+            watchdog: options::Watchdog::default(),
+            disconnect_cause: options::DisconnectCause::DoNotWantToTalkToYou,
+            stats: options::Stats::default(),
+            report: options::Report::default(),
+            identifiers: options::Identifiers::default(),
             globals: options::Global { variables: vec![] },
             dictionaries: vec![],
             scenarios: vec![],
-        };
+        }
+    }
+
+    #[test]
+    fn test_load_calculate() {
+        let options = options();
 
         let param = RunParameter::new(&options);
 
@@ -280,5 +814,202 @@ mod tests {
         assert_eq!(param.interval.as_secs_f64(), 0.004);
         assert_eq!(param.total_iterations, 30000);
         assert_eq!(param.total_requests, 60000);
+        assert_eq!(param.session_length, 1);
+    }
+
+    #[test]
+    fn test_session_length() {
+        let mut options = options();
+        let scenario = |name: &str, scenario_type, times| options::Scenario {
+            name: name.into(),
+            scenario_type,
+            times,
+            capture: Default::default(),
+            assertions: vec![],
+            message: options::Message {
+                command: "Credit-Control".into(),
+                application: "Charging Control".into(),
+                flags: Default::default(),
+                avps: vec![],
+            },
+        };
+        options.scenarios = vec![
+            scenario("CER", ScenarioType::Init, 1),
+            scenario("CCR-I", ScenarioType::Repeating, 1),
+            scenario("CCR-U", ScenarioType::Repeating, 3),
+            scenario("CCR-T", ScenarioType::Repeating, 1),
+        ];
+
+        let param = RunParameter::new(&options);
+
+        assert_eq!(param.session_length, 5);
+        assert_eq!(param.target_tps, 100);
+        assert_eq!(param.total_iterations, 12000);
+    }
+
+    #[tokio::test]
+    async fn test_open_connection_failure() {
+        let dict = Arc::new(Dictionary::new(&[&diameter::dictionary::DEFAULT_DICT_XML]));
+        let enums = EnumDictionary::default();
+        let avp = |name: &str, value: &str| options::Avp {
+            name: name.into(),
+            value: options::Value::String(value.into()),
+            ..Default::default()
+        };
+        let cer = options::Scenario {
+            name: "CER".into(),
+            scenario_type: ScenarioType::Init,
+            times: 1,
+            capture: Default::default(),
+            assertions: vec![],
+            message: options::Message {
+                command: "Capabilities-Exchange".into(),
+                application: "Base".into(),
+                flags: Default::default(),
+                avps: vec![
+                    avp("Origin-Host", "host.example.com"),
+                    avp("Origin-Realm", "realm.example.com"),
+                ],
+            },
+        };
+        let global = Global::new(&options::Global { variables: vec![] }).unwrap();
+        let mut init_scenario =
+            scenario::Scenario::new(&cer, &global, Arc::clone(&dict), &enums).unwrap();
+        let mut options = options();
+        options.call_timeout = Duration::from_millis(100);
+
+        // A peer which answers no CER, and then one which is gone
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let peer_options = options::Peer {
+            host: "127.0.0.1".into(),
+            port: listener.local_addr().unwrap().port(),
+            origin_host: None,
+            origin_realm: None,
+            destination_host: None,
+            destination_realm: None,
+            weight: 1,
+            connections: 1,
+        };
+        let peer = Peer {
+            address: peer_options.address(),
+            identity: PeerIdentity::default(),
+            responses: 0,
+            connect_failures: 0,
+            watchdogs: vec![],
+        };
+        let error = LocalSet::new()
+            .run_until(async {
+                let opened = open_connection(
+                    &peer,
+                    &peer_options,
+                    &options,
+                    Identifiers::new(None, 0),
+                    &mut init_scenario,
+                    &dict,
+                );
+                // Kept open, unanswered
+                let (_accepted, opened) = tokio::join!(listener.accept(), opened);
+                opened.err().unwrap()
+            })
+            .await;
+        assert_eq!(error, "no CEA within 100ms");
+
+        drop(listener);
+        let opened = open_connection(
+            &peer,
+            &peer_options,
+            &options,
+            Identifiers::new(None, 0),
+            &mut init_scenario,
+            &dict,
+        );
+        assert!(LocalSet::new().run_until(opened).await.is_err());
+    }
+
+    #[test]
+    fn test_record_answer() {
+        let mut options = options();
+        options.success_codes = vec![2001, 4012];
+        let live = LiveStats::default();
+        let mut peer = Peer {
+            address: "localhost:3868".into(),
+            identity: PeerIdentity::default(),
+            responses: 0,
+            connect_failures: 0,
+            watchdogs: vec![],
+        };
+        let mut report = ScenarioReport {
+            name: "CCR".into(),
+            ..Default::default()
+        };
+
+        let answer = Answer::TimedOut;
+        assert!(!record_answer(
+            answer,
+            &mut peer,
+            &mut report,
+            &live,
+            &options
+        ));
+        let error = diameter::Error::ClientError("closed".into());
+        let answer = Answer::Failed(error);
+        assert!(!record_answer(
+            answer,
+            &mut peer,
+            &mut report,
+            &live,
+            &options
+        ));
+        assert_eq!(peer.responses, 0);
+        assert_eq!(report.timeouts, 1);
+        assert_eq!(report.errors, 1);
+
+        let dict = Arc::new(Dictionary::new(&[]));
+        for code in [4012, 5030] {
+            let mut cca = DiameterMessage::new(
+                CommandCode::CreditControl,
+                ApplicationId::CreditControl,
+                0,
+                1,
+                1,
+                Arc::clone(&dict),
+            );
+            cca.add_avp(268, None, M, Unsigned32::new(code).into());
+            let answer = Answer::Received(cca, Duration::from_millis(3));
+            assert!(record_answer(
+                answer,
+                &mut peer,
+                &mut report,
+                &live,
+                &options
+            ));
+        }
+        assert_eq!(peer.responses, 2);
+        assert_eq!(report.results.codes.len(), 2);
+        assert_eq!(report.results.failures, 1);
+        assert_eq!(report.latency.count(), 2);
+
+        let mut total = ScenarioReport {
+            name: "CCR".into(),
+            requests: 3,
+            answers: 1,
+            ..Default::default()
+        };
+        total.merge(&report);
+        assert_eq!(total.requests, 3);
+        assert_eq!(total.answers, 3);
+        assert_eq!(total.timeouts, 1);
+        assert_eq!(total.errors, 1);
+        assert_eq!(total.results.failures, 1);
+
+        let mut other = ScenarioReport {
+            assertion_failures: 2,
+            ..Default::default()
+        };
+        other.failed_assertions.insert("Class present".into(), 2);
+        total.merge(&other);
+        total.merge(&other);
+        assert_eq!(total.assertion_failures, 4);
+        assert_eq!(total.failed_assertions["Class present"], 4);
     }
 }
@@ -1,36 +1,135 @@
+use crate::assertion::Assertion;
+use crate::dictionary;
+use crate::dictionary::EnumDictionary;
 use crate::global;
 use crate::global::Global;
 use crate::options;
+use base64::Engine;
 use chrono::DateTime;
 use chrono::Utc;
 use diameter::avp::Address;
 use diameter::avp::AvpType;
 use diameter::avp::AvpValue;
+use diameter::avp::DiameterURI;
 use diameter::avp::Enumerated;
+use diameter::avp::Float32;
+use diameter::avp::Float64;
 use diameter::avp::Grouped;
 use diameter::avp::IPv4;
 use diameter::avp::IPv6;
 use diameter::avp::Identity;
+use diameter::avp::Integer32;
+use diameter::avp::Integer64;
 use diameter::avp::OctetString;
 use diameter::avp::Time;
 use diameter::avp::UTF8String;
 use diameter::avp::Unsigned32;
 use diameter::avp::Unsigned64;
+use diameter::dictionary::AvpDefinition;
 use diameter::dictionary::Dictionary;
 use diameter::flags;
 use diameter::{ApplicationId, CommandCode, DiameterMessage};
 use regex::Regex;
+use std::collections::HashMap;
 use std::error::Error;
 use std::net::IpAddr;
 use std::net::Ipv4Addr;
 use std::net::Ipv6Addr;
 use std::result::Result;
 use std::sync::Arc;
-use uuid::Uuid;
+
+/// Built-in variable, the number of the request within its session, starting at 0.
+pub const REQUEST_NUMBER: &str = "REQUEST_NUMBER";
+
+/// Values captured from answers, by variable name.
+pub type Captured = HashMap<String, AvpValue>;
+
+/// State of one call flow, shared by the messages of all its steps.
+#[derive(Debug, Default)]
+pub struct Session {
+    values: HashMap<String, String>,
+    captured: Captured,
+    /// Captured from the CEA of the connection carrying the session
+    init: Arc<Captured>,
+    /// Whether a built message used a value of `init`
+    used_init: bool,
+    request_number: u32,
+    /// Rows taken from the session scoped feeds
+    rows: HashMap<String, usize>,
+    /// Rows taken from the message scoped feeds, for the message being built
+    message_rows: HashMap<String, usize>,
+}
+
+impl Session {
+    /// Values captured by the session, e.g. the CEA of a connection.
+    pub fn into_captured(self) -> Captured {
+        self.captured
+    }
+
+    /// Sets the values captured from the CEA of the connection carrying the session.
+    pub fn set_init(&mut self, init: Arc<Captured>) {
+        self.init = init;
+    }
+
+    /// Whether the messages built so far used values captured from the CEA.
+    pub fn used_init(&self) -> bool {
+        self.used_init
+    }
+
+    /// A captured value, from the answers of the session first, then from the CEA.
+    fn captured(&mut self, name: &str) -> Option<&AvpValue> {
+        if self.captured.contains_key(name) {
+            return self.captured.get(name);
+        }
+        let value = self.init.get(name)?;
+        self.used_init = true;
+        Some(value)
+    }
+
+    /// Value of a session scoped variable, generated on first use.
+    fn value(&mut self, variable: &global::Variable) -> Result<String, Box<dyn Error>> {
+        if let Some(value) = self.values.get(&variable.name) {
+            return Ok(value.clone());
+        }
+        let value = variable.value.get(&self.context_for(variable))?;
+        self.values.insert(variable.name.clone(), value.clone());
+        Ok(value)
+    }
+
+    /// Row of `feed` for the current message or session, taken on first use.
+    fn row(&mut self, feed: &global::Feed) -> usize {
+        let rows = match feed.scope {
+            options::Scope::Message => &mut self.message_rows,
+            options::Scope::Session => &mut self.rows,
+        };
+        *rows
+            .entry(feed.name.clone())
+            .or_insert_with(|| feed.next_row())
+    }
+
+    /// Context generating `variable`, which may use the values captured from the CEA.
+    fn context_for(&mut self, variable: &global::Variable) -> global::Context<'_> {
+        if variable.value.reads_captured() && !self.init.is_empty() {
+            self.used_init = true;
+        }
+        self.context()
+    }
+
+    fn context(&self) -> global::Context<'_> {
+        global::Context {
+            request_number: self.request_number,
+            values: &self.values,
+            captured: &self.captured,
+            init: &self.init,
+        }
+    }
+}
 
 pub struct Scenario<'a> {
     name: String,
     message: Message<'a>,
+    captures: Vec<Capture>,
+    assertions: Vec<Assertion>,
 }
 
 impl<'a> Scenario<'a> {
@@ -38,15 +137,57 @@ impl<'a> Scenario<'a> {
         options: &options::Scenario,
         global: &'a Global,
         dict: Arc<Dictionary>,
+        enums: &EnumDictionary,
     ) -> Result<Self, Box<dyn Error>> {
+        let mut captures = vec![];
+        for (variable, path) in &options.capture {
+            captures.push(Capture::new(variable, path, &dict)?);
+        }
+        let mut assertions = vec![];
+        for assertion in &options.assertions {
+            let assertion = Assertion::new(assertion, &dict)
+                .map_err(|e| format!("assertion {}: {}", assertions.len() + 1, e))?;
+            assertions.push(assertion);
+        }
         return Ok(Scenario {
             name: options.name.clone(),
-            message: Message::new(options, global, dict)?,
+            message: Message::new(options, global, dict, enums)?,
+            captures,
+            assertions,
         });
     }
 
-    pub fn next_message(&mut self) -> Result<DiameterMessage, Box<dyn Error>> {
-        self.message.message()
+    /// Assertions failed by `answer`, with the reason of each failure.
+    pub fn check(&self, answer: &DiameterMessage) -> Vec<(&str, String)> {
+        self.assertions
+            .iter()
+            .filter_map(|assertion| Some((assertion.name(), assertion.check(answer)?)))
+            .collect()
+    }
+
+    /// Stores the captured AVPs of `answer` in the session, missing AVPs are left unset.
+    pub fn capture(&self, answer: &DiameterMessage, session: &mut Session) {
+        for capture in &self.captures {
+            match capture.path.find(answer) {
+                Some(value) => {
+                    session
+                        .captured
+                        .insert(capture.variable.clone(), value.clone());
+                }
+                None => log::debug!(
+                    "Scenario {}: no AVP to capture in {}",
+                    self.name,
+                    capture.variable
+                ),
+            }
+        }
+    }
+
+    pub fn next_message(
+        &mut self,
+        session: &mut Session,
+    ) -> Result<DiameterMessage, Box<dyn Error>> {
+        self.message.message(session)
     }
 
     pub fn get_name(&self) -> &str {
@@ -58,7 +199,6 @@ pub struct Message<'a> {
     command_code: CommandCode,
     application_id: ApplicationId,
     flags: u8,
-    seq_num: u32,
     avps: Vec<Avp<'a>>,
     dict: Arc<Dictionary>,
 }
@@ -68,8 +208,8 @@ impl<'a> Message<'a> {
         scenario: &options::Scenario,
         global: &'a Global,
         dict: Arc<Dictionary>,
+        enums: &EnumDictionary,
     ) -> Result<Self, Box<dyn Error>> {
-
         let command_code = dict
             .get_command_code_by_name(&scenario.message.command)
             .ok_or(format!(
@@ -84,62 +224,40 @@ impl<'a> Message<'a> {
                 scenario.message.application
             ))?;
 
-        let flags = flags::REQUEST;
+        let flags = message_flags(&scenario.message.flags);
 
         let mut avps = vec![];
-
         for a in &scenario.message.avps {
-            let avp_definition = dict
-                .get_avp_by_name(&a.name)
-                .ok_or(format!("AVP '{}' not found in dictionary", a.name))?;
-
-            let value = Value::new(&a.value, avp_definition.avp_type, global, Arc::clone(&dict))
-                .map_err(|e| format!("AVP '{}', error: {}", avp_definition.name, e.to_string()))?;
-
-            let avp_flags = if avp_definition.m_flag {
-                diameter::avp::flags::M
-            } else {
-                0
-            };
-            let avp = Avp {
-                code: avp_definition.code,
-                vendor_id: avp_definition.vendor_id,
-                flags: avp_flags,
-                value,
-            };
-            avps.push(avp);
+            avps.push(Avp::new(a, global, &dict, enums)?);
         }
 
         Ok(Message {
             command_code,
             application_id,
             flags,
-            seq_num: 0,
             avps,
             dict,
         })
     }
 
-    pub fn message(&mut self) -> Result<DiameterMessage, Box<dyn Error>> {
-        self.seq_num += 1;
-        // TODO remove this
-        let seq_num = Uuid::new_v4().as_u128() as u32;
+    /// The identifiers are left to 0, they are given by the connection sending the message.
+    pub fn message(&mut self, session: &mut Session) -> Result<DiameterMessage, Box<dyn Error>> {
+        session.message_rows.clear();
 
         let mut diameter_msg = DiameterMessage::new(
             self.command_code,
             self.application_id,
             self.flags,
-            seq_num,
-            seq_num,
-            // self.seq_num,
-            // self.seq_num,
+            0,
+            0,
             Arc::clone(&self.dict),
         );
 
         for avp in &self.avps {
-            let value = avp.value.get_value()?;
+            let value = avp.value.get_value(session)?;
             diameter_msg.add_avp(avp.code, avp.vendor_id, avp.flags, value);
         }
+        session.request_number += 1;
 
         Ok(diameter_msg)
     }
@@ -151,34 +269,31 @@ pub fn string_to_avp_value(
 ) -> Result<AvpValue, Box<dyn Error>> {
     let value = match avp_type {
         AvpType::Address => {
-            let addr: IpAddr = str.parse().expect("Invalid IP address");
+            let addr: IpAddr = str.parse()?;
             match addr {
                 IpAddr::V4(addr) => Address::from_ipv4(addr).into(),
                 IpAddr::V6(addr) => Address::from_ipv6(addr).into(),
             }
         }
         AvpType::AddressIPv4 => {
-            let addr: Ipv4Addr = str.parse().expect("Invalid IPv4 address");
+            let addr: Ipv4Addr = str.parse()?;
             IPv4::new(addr).into()
         }
         AvpType::AddressIPv6 => {
-            let addr: Ipv6Addr = str.parse().expect("Invalid IPv6 address");
+            let addr: Ipv6Addr = str.parse()?;
             IPv6::new(addr).into()
         }
-        AvpType::Identity => Identity::new(&str).into(),
-        AvpType::DiameterURI => UTF8String::new(&str).into(),
+        AvpType::Identity => Identity::new(str).into(),
+        AvpType::DiameterURI => DiameterURI::new(str.as_bytes().to_vec()).into(),
         AvpType::Enumerated => Enumerated::new(str.parse()?).into(),
-        AvpType::Float32 => Unsigned32::new(str.parse()?).into(),
-        AvpType::Float64 => Unsigned64::new(str.parse()?).into(),
-        AvpType::Integer32 => Unsigned32::new(str.parse()?).into(),
-        AvpType::Integer64 => Unsigned64::new(str.parse()?).into(),
-        AvpType::OctetString => {
-            // TODO
-            OctetString::new(str.as_bytes().to_vec()).into()
-        }
+        AvpType::Float32 => Float32::new(str.parse()?).into(),
+        AvpType::Float64 => Float64::new(str.parse()?).into(),
+        AvpType::Integer32 => Integer32::new(str.parse()?).into(),
+        AvpType::Integer64 => Integer64::new(str.parse()?).into(),
+        AvpType::OctetString => OctetString::new(octets(str)?).into(),
         AvpType::Unsigned32 => Unsigned32::new(str.parse()?).into(),
         AvpType::Unsigned64 => Unsigned64::new(str.parse()?).into(),
-        AvpType::UTF8String => UTF8String::new(&str).into(),
+        AvpType::UTF8String => UTF8String::new(str).into(),
         AvpType::Time => {
             let time = str.parse::<DateTime<Utc>>()?;
             Time::new(time).into()
@@ -189,6 +304,146 @@ pub fn string_to_avp_value(
     Ok(value)
 }
 
+/// Value of an AVP with the items `enums` of its dictionary, an Enumerated value is
+/// either a number or the name of an item, e.g. `INITIAL_REQUEST`.
+pub fn named_avp_value(
+    str: &str,
+    avp_type: diameter::avp::AvpType,
+    enums: Option<&dictionary::Enums>,
+) -> Result<AvpValue, Box<dyn Error>> {
+    if avp_type == AvpType::Enumerated && str.parse::<i32>().is_err() {
+        let value = enums
+            .and_then(|enums| enums.get(str))
+            .ok_or(format!("unknown Enumerated value '{}'", str))?;
+        return Ok(Enumerated::new(*value).into());
+    }
+    string_to_avp_value(str, avp_type)
+}
+
+/// Bytes of an OctetString, written as `hex:0a0b`, `base64:CgsM` or `plain:text`.
+/// Text without any of these prefixes is taken as is.
+fn octets(str: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    if let Some(hex) = str.strip_prefix("hex:") {
+        let digits: Vec<u32> = hex
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .map(|c| c.to_digit(16))
+            .collect::<Option<_>>()
+            .ok_or(format!("invalid hex digits in '{}'", str))?;
+        if !digits.len().is_multiple_of(2) {
+            return Err(format!("odd number of hex digits in '{}'", str).into());
+        }
+        Ok(digits
+            .chunks(2)
+            .map(|pair| (pair[0] * 16 + pair[1]) as u8)
+            .collect())
+    } else if let Some(encoded) = str.strip_prefix("base64:") {
+        Ok(base64::engine::general_purpose::STANDARD.decode(encoded)?)
+    } else {
+        let plain = str.strip_prefix("plain:").unwrap_or(str);
+        Ok(plain.as_bytes().to_vec())
+    }
+}
+
+/// Names of the `${VAR}` variables referenced in a value.
+pub fn variable_names(source: &str) -> Vec<String> {
+    let variable_pattern = Regex::new(r"\$\{([^}]+)\}").unwrap();
+    variable_pattern
+        .captures_iter(source)
+        .map(|caps| caps[1].to_string())
+        .collect()
+}
+
+/// AVP of an answer stored in a session variable.
+struct Capture {
+    variable: String,
+    path: AvpPath,
+}
+
+impl Capture {
+    fn new(variable: &str, path: &str, dict: &Dictionary) -> Result<Self, Box<dyn Error>> {
+        Ok(Capture {
+            variable: variable.into(),
+            path: AvpPath::new(path, dict)?,
+        })
+    }
+}
+
+/// Location of an AVP in a message, from the top level down into grouped AVPs.
+pub struct AvpPath {
+    /// Code and Vendor-Id of each AVP
+    codes: Vec<(u32, Option<u32>)>,
+}
+
+impl AvpPath {
+    /// `path` is made of AVP names separated by dots, e.g. `Granted-Service-Unit.CC-Time`.
+    pub fn new(path: &str, dict: &Dictionary) -> Result<Self, Box<dyn Error>> {
+        let names: Vec<&str> = path.split('.').collect();
+        let mut codes = vec![];
+        for (i, name) in names.iter().enumerate() {
+            let definition = dict
+                .get_avp_by_name(name)
+                .ok_or(format!("AVP '{}' not found in dictionary", name))?;
+            if i + 1 < names.len() && definition.avp_type != AvpType::Grouped {
+                return Err(format!("AVP '{}' is not Grouped", name).into());
+            }
+            codes.push((definition.code, definition.vendor_id));
+        }
+        Ok(AvpPath { codes })
+    }
+
+    /// Value of the first AVP found at this path.
+    pub fn find<'m>(&self, message: &'m DiameterMessage) -> Option<&'m AvpValue> {
+        find_avp(message.get_avps(), &self.codes)
+    }
+}
+
+fn find_avp<'m>(
+    avps: &'m [diameter::avp::Avp],
+    codes: &[(u32, Option<u32>)],
+) -> Option<&'m AvpValue> {
+    let ((code, vendor_id), rest) = codes.split_first()?;
+    let avp = avps
+        .iter()
+        .find(|avp| avp.get_code() == *code && avp.get_vendor_id() == *vendor_id)?;
+    if rest.is_empty() {
+        Some(avp.get_value())
+    } else {
+        find_avp(avp.get_grouped()?.avps(), rest)
+    }
+}
+
+/// Text of an AVP value, as it would be written in the options.
+pub fn avp_value_to_string(value: &AvpValue) -> String {
+    match value {
+        AvpValue::OctetString(v) => String::from_utf8_lossy(v.value()).into(),
+        AvpValue::DiameterURI(v) => String::from_utf8_lossy(v.value()).into(),
+        v => v.to_string(),
+    }
+}
+
+/// Type of an AVP value.
+pub fn value_type(value: &AvpValue) -> AvpType {
+    match value {
+        AvpValue::Address(_) => AvpType::Address,
+        AvpValue::AddressIPv4(_) => AvpType::AddressIPv4,
+        AvpValue::AddressIPv6(_) => AvpType::AddressIPv6,
+        AvpValue::Identity(_) => AvpType::Identity,
+        AvpValue::DiameterURI(_) => AvpType::DiameterURI,
+        AvpValue::Enumerated(_) => AvpType::Enumerated,
+        AvpValue::Float32(_) => AvpType::Float32,
+        AvpValue::Float64(_) => AvpType::Float64,
+        AvpValue::Grouped(_) => AvpType::Grouped,
+        AvpValue::Integer32(_) => AvpType::Integer32,
+        AvpValue::Integer64(_) => AvpType::Integer64,
+        AvpValue::OctetString(_) => AvpType::OctetString,
+        AvpValue::Time(_) => AvpType::Time,
+        AvpValue::Unsigned32(_) => AvpType::Unsigned32,
+        AvpValue::Unsigned64(_) => AvpType::Unsigned64,
+        AvpValue::UTF8String(_) => AvpType::UTF8String,
+    }
+}
+
 struct Avp<'a> {
     code: u32,
     vendor_id: Option<u32>,
@@ -196,34 +451,209 @@ struct Avp<'a> {
     value: Value<'a>,
 }
 
+impl<'a> Avp<'a> {
+    fn new(
+        avp: &options::Avp,
+        global: &'a Global,
+        dict: &Arc<Dictionary>,
+        enums: &EnumDictionary,
+    ) -> Result<Self, Box<dyn Error>> {
+        let definition = avp_definition(avp, dict)
+            .map_err(|e| format!("AVP '{}', error: {}", avp_label(avp), e))?;
+        let (vendor_id, flags) = avp_flags(&definition, &avp.flags)
+            .map_err(|e| format!("AVP '{}', error: {}", definition.name, e))?;
+        let value = Value::new(&avp.value, &definition, global, Arc::clone(dict), enums)
+            .map_err(|e| format!("AVP '{}', error: {}", definition.name, e))?;
+
+        Ok(Avp {
+            code: definition.code,
+            vendor_id,
+            flags,
+            value,
+        })
+    }
+}
+
+/// Definition of an AVP of a message, from the dictionary with the overrides of the
+/// options, or made of them for a raw AVP.
+pub fn avp_definition(avp: &options::Avp, dict: &Dictionary) -> Result<AvpDefinition, String> {
+    let mut definition = match avp.code {
+        Some(code) => AvpDefinition {
+            code,
+            vendor_id: None,
+            name: avp_label(avp),
+            avp_type: AvpType::OctetString,
+            m_flag: false,
+        },
+        None if avp.name.is_empty() => return Err("needs a name or a code".into()),
+        None => dict
+            .get_avp_by_name(&avp.name)
+            .ok_or("not found in dictionary")?
+            .clone(),
+    };
+    if let Some(vendor_id) = avp.vendor_id {
+        definition.vendor_id = Some(vendor_id);
+    }
+    if let Some(name) = &avp.avp_type {
+        definition.avp_type =
+            dictionary::avp_type(name).ok_or(format!("unknown type '{}'", name))?;
+    }
+    Ok(definition)
+}
+
+/// Name of an AVP in errors, its code when raw without a name.
+pub fn avp_label(avp: &options::Avp) -> String {
+    match avp.code {
+        Some(code) if avp.name.is_empty() => code.to_string(),
+        _ => avp.name.clone(),
+    }
+}
+
+/// Command flags of a message header.
+pub fn message_flags(options: &options::MessageFlags) -> u8 {
+    let options = match options {
+        // The legacy default, 0, was sent as a request
+        options::MessageFlags::Bits(0) => return flags::REQUEST,
+        options::MessageFlags::Bits(bits) => return *bits,
+        options::MessageFlags::Named(options) => options,
+    };
+    let mut bits = 0;
+    for (set, bit) in [
+        (options.request, flags::REQUEST),
+        (options.proxiable, flags::PROXYABLE),
+        (options.error, flags::ERROR),
+        (options.retransmit, flags::RETRANSMIT),
+    ] {
+        if set {
+            bits |= bit;
+        }
+    }
+    bits
+}
+
+/// Vendor-Id and flags of an AVP, from its dictionary definition and the overrides.
+pub fn avp_flags(
+    definition: &AvpDefinition,
+    options: &options::AvpFlags,
+) -> Result<(Option<u32>, u8), String> {
+    let vendor_id = match options.vendor {
+        Some(false) => None,
+        Some(true) if definition.vendor_id.is_none() => {
+            return Err("the V bit needs a Vendor-Id, set vendor_id".into())
+        }
+        _ => definition.vendor_id,
+    };
+    let mut bits = 0;
+    if options.mandatory.unwrap_or(definition.m_flag) {
+        bits |= diameter::avp::flags::M;
+    }
+    if options.protected.unwrap_or(false) {
+        bits |= diameter::avp::flags::P;
+    }
+    Ok((vendor_id, bits))
+}
+
 struct Value<'a> {
     source: String,
     avp_type: diameter::avp::AvpType,
+    enums: Option<Arc<dictionary::Enums>>,
     variables: Vec<&'a global::Variable>,
+    captures: Vec<String>,
+    feeds: Vec<FeedColumn<'a>>,
+    request_number: bool,
+    group: Option<Group<'a>>,
     constant: Option<AvpValue>,
 }
 
+/// Children of a grouped value, evaluated again for each message.
+struct Group<'a> {
+    avps: Vec<Avp<'a>>,
+    dict: Arc<Dictionary>,
+}
+
+impl Group<'_> {
+    fn is_constant(&self) -> bool {
+        self.avps.iter().all(|avp| avp.value.constant.is_some())
+    }
+
+    fn get_value(&self, session: &mut Session) -> Result<AvpValue, Box<dyn Error>> {
+        let mut avps = vec![];
+        for avp in &self.avps {
+            let value = avp.value.get_value(session)?;
+            avps.push(diameter::avp::Avp::new(
+                avp.code,
+                avp.vendor_id,
+                avp.flags,
+                value,
+                Arc::clone(&self.dict),
+            ));
+        }
+        Ok(Grouped::new(avps, Arc::clone(&self.dict)).into())
+    }
+}
+
+/// `${FEED.column}` in a value.
+struct FeedColumn<'a> {
+    name: String,
+    feed: &'a global::Feed,
+    column: usize,
+}
+
 impl<'a> Value<'a> {
     pub fn new(
         source: &options::Value,
-        avp_type: diameter::avp::AvpType,
+        definition: &AvpDefinition,
         global: &'a Global,
         dict: Arc<Dictionary>,
+        enum_dict: &EnumDictionary,
     ) -> Result<Self, Box<dyn Error>> {
+        let avp_type = definition.avp_type;
+        let enums = match avp_type {
+            AvpType::Enumerated => enum_dict.get(definition.code, definition.vendor_id),
+            _ => None,
+        };
         match source {
             options::Value::String(source) => {
                 // Scan for variables
-                let variable_pattern = Regex::new(r"\$\{([^}]+)\}")?;
                 let mut variables = vec![];
-                for caps in variable_pattern.captures_iter(source) {
-                    let cap = caps[1].to_string();
-                    let var = global.get_variable(&cap).unwrap();
-                    variables.push(var);
+                let mut captures = vec![];
+                let mut feeds = vec![];
+                let mut request_number = false;
+                for name in variable_names(source) {
+                    if name == REQUEST_NUMBER {
+                        request_number = true;
+                    } else if let Some(var) = global.get_variable(&name) {
+                        variables.push(var);
+                    } else if global.is_captured(&name) {
+                        captures.push(name);
+                    } else if global.get_feed(&name).is_some() {
+                        return Err(format!(
+                            "Feed '{}' is used by column, as ${{{}.column}}",
+                            name, name
+                        )
+                        .into());
+                    } else if let Some(feed) = name
+                        .split_once('.')
+                        .and_then(|(feed, _)| global.get_feed(feed))
+                    {
+                        let (_, column) = name.split_once('.').unwrap();
+                        let column = feed.column(column).ok_or(format!(
+                            "Column '{}' not found in feed '{}'",
+                            column, feed.name
+                        ))?;
+                        feeds.push(FeedColumn { name, feed, column });
+                    } else {
+                        return Err(format!("Undefined variable '{}'", name).into());
+                    }
                 }
 
                 // If no variable found, make this a constant
-                let constant = if variables.is_empty() {
-                    let value = string_to_avp_value(source, avp_type)?;
+                let constant = if variables.is_empty()
+                    && captures.is_empty()
+                    && feeds.is_empty()
+                    && !request_number
+                {
+                    let value = named_avp_value(source, avp_type, enums.as_deref())?;
                     Some(value)
                 } else {
                     None
@@ -232,41 +662,40 @@ impl<'a> Value<'a> {
                 Ok(Value {
                     source: source.into(),
                     avp_type,
+                    enums,
                     variables,
+                    captures,
+                    feeds,
+                    request_number,
+                    group: None,
                     constant,
                 })
             }
             options::Value::Avp(source) => {
-                let variables = vec![];
                 if avp_type != AvpType::Grouped {
                     return Err("Invalid AVP type for AVP value".into());
                 }
                 let mut avps = vec![];
                 for a in source {
-
-                    let avp_definition = dict
-                        .get_avp_by_name(&a.name)
-                        .ok_or(format!("AVP '{}' not found in dictionary", a.name))?;
-
-                    let value =
-                        Value::new(&a.value, avp_definition.avp_type, global, Arc::clone(&dict))?;
-                    let value = value.get_value()?;
-                    let avp = diameter::avp::Avp::new(
-                        avp_definition.code,
-                        avp_definition.vendor_id,
-                        0,
-                        value,
-                        Arc::clone(&dict),
-                    );
-
-                    avps.push(avp);
+                    avps.push(Avp::new(a, global, &dict, enum_dict)?);
                 }
-                let value: AvpValue = Grouped::new(avps, dict).into();
-                let constant = Some(value);
+                let group = Group { avps, dict };
+
+                // Nested variables make the whole group a template
+                let constant = if group.is_constant() {
+                    Some(group.get_value(&mut Session::default())?)
+                } else {
+                    None
+                };
                 Ok(Value {
-                    source: "TODO".into(),
+                    source: String::new(),
                     avp_type,
-                    variables,
+                    enums,
+                    variables: vec![],
+                    captures: vec![],
+                    feeds: vec![],
+                    request_number: false,
+                    group: Some(group),
                     constant,
                 })
             }
@@ -274,21 +703,60 @@ impl<'a> Value<'a> {
     }
 
     // TODO Rename
-    fn compute(&self) -> String {
+    fn compute(&self, session: &mut Session) -> Result<String, Box<dyn Error>> {
         let mut result: String = self.source.clone();
         for v in &self.variables {
-            let counter = v.value.get();
+            let counter = match v.scope {
+                options::Scope::Message => v.value.get(&session.context_for(v))?,
+                options::Scope::Session => session.value(v)?,
+            };
             let name = &v.name;
             result = result.replace(&format!("${{{}}}", name), &counter);
         }
-        result
+        for name in &self.captures {
+            let value = session
+                .captured(name)
+                .ok_or(format!("Variable '{}' has not been captured", name))?;
+            result = result.replace(&format!("${{{}}}", name), &avp_value_to_string(value));
+        }
+        for f in &self.feeds {
+            let row = session.row(f.feed);
+            result = result.replace(&format!("${{{}}}", f.name), f.feed.cell(row, f.column));
+        }
+        if self.request_number {
+            result = result.replace(
+                &format!("${{{}}}", REQUEST_NUMBER),
+                &session.request_number.to_string(),
+            );
+        }
+        Ok(result)
     }
 
-    pub fn get_value(&self) -> Result<AvpValue, Box<dyn Error>> {
-        match &self.constant {
-            Some(v) => Ok(v.clone()),
-            None => Ok(string_to_avp_value(&self.compute(), self.avp_type)?),
+    pub fn get_value(&self, session: &mut Session) -> Result<AvpValue, Box<dyn Error>> {
+        if let Some(v) = &self.constant {
+            return Ok(v.clone());
         }
+        if let Some(group) = &self.group {
+            return group.get_value(session);
+        }
+
+        // A value made of a single capture of the same type is copied as is,
+        // binary values such as Class or State are kept intact
+        if let [name] = self.captures.as_slice() {
+            if self.source == format!("${{{}}}", name) {
+                if let Some(v) = session.captured(name) {
+                    if value_type(v) == self.avp_type {
+                        return Ok(v.clone());
+                    }
+                }
+            }
+        }
+
+        named_avp_value(
+            &self.compute(session)?,
+            self.avp_type,
+            self.enums.as_deref(),
+        )
     }
 }
 
@@ -297,9 +765,23 @@ mod tests {
     use super::*;
     use crate::options;
 
+    fn enums() -> EnumDictionary {
+        EnumDictionary::new(&[&diameter::dictionary::DEFAULT_DICT_XML])
+    }
+
+    fn definition(avp_type: AvpType) -> AvpDefinition {
+        AvpDefinition {
+            code: 0,
+            vendor_id: None,
+            name: "Test".into(),
+            avp_type,
+            m_flag: false,
+        }
+    }
+
     #[test]
     fn test_constant() {
-        let dict = Dictionary::new(&vec![]);
+        let dict = Dictionary::new(&[]);
         let dict = Arc::new(dict);
 
         let global = Global::new(&options::Global {
@@ -310,27 +792,40 @@ mod tests {
                     min: 1,
                     max: 5,
                     step: 3,
+                    scope: options::Scope::Message,
+                    ..Default::default()
                 },
             ))
             .collect()],
-        });
+        })
+        .unwrap();
 
         let variable = Value::new(
             &options::Value::String("example.origin.host".into()),
-            AvpType::UTF8String,
+            &definition(AvpType::UTF8String),
             &global,
             dict,
+            &EnumDictionary::default(),
         )
         .unwrap();
 
-        assert_eq!("example.origin.host", variable.compute());
-        assert_eq!("example.origin.host", variable.compute());
-        assert_eq!("example.origin.host", variable.compute());
+        assert_eq!(
+            "example.origin.host",
+            variable.compute(&mut Session::default()).unwrap()
+        );
+        assert_eq!(
+            "example.origin.host",
+            variable.compute(&mut Session::default()).unwrap()
+        );
+        assert_eq!(
+            "example.origin.host",
+            variable.compute(&mut Session::default()).unwrap()
+        );
     }
 
     #[test]
     fn test_counter_variable() {
-        let dict = Dictionary::new(&vec![]);
+        let dict = Dictionary::new(&[]);
         let dict = Arc::new(dict);
 
         let global = Global::new(&options::Global {
@@ -341,27 +836,31 @@ mod tests {
                     min: 1,
                     max: 5,
                     step: 3,
+                    scope: options::Scope::Message,
+                    ..Default::default()
                 },
             ))
             .collect()],
-        });
+        })
+        .unwrap();
 
         let variable = Value::new(
             &options::Value::String("ses;${COUNTER}".into()),
-            AvpType::UTF8String,
+            &definition(AvpType::UTF8String),
             &global,
             dict,
+            &EnumDictionary::default(),
         )
         .unwrap();
 
-        assert_eq!("ses;1", variable.compute());
-        assert_eq!("ses;4", variable.compute());
-        assert_eq!("ses;1", variable.compute());
+        assert_eq!("ses;1", variable.compute(&mut Session::default()).unwrap());
+        assert_eq!("ses;4", variable.compute(&mut Session::default()).unwrap());
+        assert_eq!("ses;1", variable.compute(&mut Session::default()).unwrap());
     }
 
     #[test]
     fn test_2_counters_variable() {
-        let dict = Dictionary::new(&vec![]);
+        let dict = Dictionary::new(&[]);
         let dict = Arc::new(dict);
 
         let global = Global::new(&options::Global {
@@ -373,6 +872,8 @@ mod tests {
                         min: 0,
                         max: 5,
                         step: 1,
+                        scope: options::Scope::Message,
+                        ..Default::default()
                     },
                 ))
                 .collect(),
@@ -383,22 +884,818 @@ mod tests {
                         min: 1,
                         max: 5,
                         step: 3,
+                        scope: options::Scope::Message,
+                        ..Default::default()
                     },
                 ))
                 .collect(),
             ],
-        });
+        })
+        .unwrap();
 
         let variable = Value::new(
             &options::Value::String("ses;${COUNTER1}_${COUNTER2}".into()),
-            AvpType::UTF8String,
+            &definition(AvpType::UTF8String),
             &global,
             dict,
+            &EnumDictionary::default(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            "ses;0_1",
+            variable.compute(&mut Session::default()).unwrap()
+        );
+        assert_eq!(
+            "ses;1_4",
+            variable.compute(&mut Session::default()).unwrap()
+        );
+        assert_eq!(
+            "ses;2_1",
+            variable.compute(&mut Session::default()).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_session_variable() {
+        let dict = Arc::new(Dictionary::new(&[]));
+        let variable = |scope| options::Variable {
+            func: options::Function::IncrementalCounter,
+            min: 1,
+            max: 100,
+            step: 1,
+            scope,
+            ..Default::default()
+        };
+        let global = Global::new(&options::Global {
+            variables: vec![[
+                ("SESSION".into(), variable(options::Scope::Session)),
+                ("MESSAGE".into(), variable(options::Scope::Message)),
+            ]
+            .into()],
+        })
+        .unwrap();
+
+        let value = Value::new(
+            &options::Value::String("${SESSION}/${MESSAGE}/${REQUEST_NUMBER}".into()),
+            &definition(AvpType::UTF8String),
+            &global,
+            dict,
+            &EnumDictionary::default(),
+        )
+        .unwrap();
+
+        let mut session = Session::default();
+        assert_eq!("1/1/0", value.compute(&mut session).unwrap());
+        session.request_number += 1;
+        assert_eq!("1/2/1", value.compute(&mut session).unwrap());
+
+        let mut session = Session::default();
+        assert_eq!("2/3/0", value.compute(&mut session).unwrap());
+    }
+
+    #[test]
+    fn test_script_variable() {
+        let dict = Arc::new(Dictionary::new(&[]));
+        let variable = |scope, script: &str| options::Variable {
+            func: options::Function::CustomScript,
+            scope,
+            script: Some(script.into()),
+            ..Default::default()
+        };
+        let imsi = r#"
+            local n = 0
+            return function()
+                n = n + 1
+                return string.format("00101%010d", n)
+            end
+        "#;
+        let suffix = r#"
+            return function(session)
+                if session.request_number > 1 then error("too many requests") end
+                return session.variables.IMSI:sub(-2) .. "-" .. session.request_number
+            end
+        "#;
+        let global = Global::new(&options::Global {
+            variables: vec![[
+                ("IMSI".into(), variable(options::Scope::Session, imsi)),
+                ("SUFFIX".into(), variable(options::Scope::Message, suffix)),
+            ]
+            .into()],
+        })
+        .unwrap();
+
+        let value = Value::new(
+            &options::Value::String("${IMSI}/${SUFFIX}".into()),
+            &definition(AvpType::UTF8String),
+            &global,
+            dict,
+            &EnumDictionary::default(),
+        )
+        .unwrap();
+
+        let mut session = Session::default();
+        assert_eq!("001010000000001/01-0", value.compute(&mut session).unwrap());
+        session.request_number += 1;
+        assert_eq!("001010000000001/01-1", value.compute(&mut session).unwrap());
+        session.request_number += 1;
+        assert_eq!(
+            value.compute(&mut session).unwrap_err().to_string(),
+            "runtime error: [string \"script\"]:3: too many requests"
+        );
+
+        let mut session = Session::default();
+        assert_eq!("001010000000002/02-0", value.compute(&mut session).unwrap());
+
+        let error = |script: &str| {
+            let variable = variable(options::Scope::Message, script);
+            let global = options::Global {
+                variables: vec![[("X".into(), variable)].into()],
+            };
+            Global::new(&global).err().unwrap().to_string()
+        };
+        assert_eq!(
+            error("return 1"),
+            "Variable 'X': the script must return a function, not integer"
+        );
+        assert!(error("return function(").starts_with("Variable 'X': syntax error"));
+    }
+
+    #[test]
+    fn test_capture() {
+        let dict = Arc::new(Dictionary::new(&[&diameter::dictionary::DEFAULT_DICT_XML]));
+        let avp = |name: &str, value: &str| options::Avp {
+            name: name.into(),
+            value: options::Value::String(value.into()),
+            ..Default::default()
+        };
+        let options = options::Scenario {
+            name: "CCR-U".into(),
+            scenario_type: options::ScenarioType::Repeating,
+            times: 1,
+            message: options::Message {
+                command: "Credit-Control".into(),
+                application: "Charging Control".into(),
+                flags: Default::default(),
+                avps: vec![
+                    avp("Session-Id", "${SID}"),
+                    avp("Class", "${CLASS}"),
+                    avp("CC-Request-Number", "${OCTETS}"),
+                ],
+            },
+            assertions: vec![],
+            capture: [
+                ("SID".to_string(), "Session-Id".to_string()),
+                ("CLASS".to_string(), "Class".to_string()),
+                (
+                    "OCTETS".to_string(),
+                    "Granted-Service-Unit.CC-Total-Octets".to_string(),
+                ),
+            ]
+            .into(),
+        };
+        let global = Global::new(&options::Global { variables: vec![] })
+            .unwrap()
+            .with_captures(std::slice::from_ref(&options));
+        let mut scenario = Scenario::new(&options, &global, Arc::clone(&dict), &enums()).unwrap();
+
+        let mut session = Session::default();
+        let error = scenario.next_message(&mut session).err().unwrap();
+        assert_eq!(error.to_string(), "Variable 'SID' has not been captured");
+
+        let mut answer = DiameterMessage::new(
+            CommandCode::CreditControl,
+            ApplicationId::CreditControl,
+            0,
+            1,
+            1,
+            Arc::clone(&dict),
+        );
+        answer.add_avp(263, None, 0, UTF8String::new("srv;1").into());
+        answer.add_avp(25, None, 0, OctetString::new(vec![0xff, 0x00]).into());
+        let gsu = diameter::avp::Avp::new(
+            421,
+            None,
+            0,
+            Unsigned64::new(1024).into(),
+            Arc::clone(&dict),
+        );
+        answer.add_avp(
+            431,
+            None,
+            0,
+            Grouped::new(vec![gsu], Arc::clone(&dict)).into(),
+        );
+        scenario.capture(&answer, &mut session);
+
+        let request = scenario.next_message(&mut session).unwrap();
+        let value = |code| request.get_avp(code).unwrap().get_value().to_string();
+        assert_eq!(value(263), "srv;1");
+        // Binary value copied as is
+        assert_eq!(value(25), "ff 00");
+        assert_eq!(value(415), "1024");
+        assert!(!session.used_init());
+    }
+
+    #[test]
+    fn test_capture_from_init() {
+        let dict = Arc::new(Dictionary::new(&[&diameter::dictionary::DEFAULT_DICT_XML]));
+        let options = options::Scenario {
+            name: "CCR-I".into(),
+            scenario_type: options::ScenarioType::Repeating,
+            times: 1,
+            message: options::Message {
+                command: "Credit-Control".into(),
+                application: "Charging Control".into(),
+                flags: Default::default(),
+                avps: vec![options::Avp {
+                    name: "Destination-Host".into(),
+                    value: options::Value::String("${HOST}".into()),
+                    ..Default::default()
+                }],
+            },
+            assertions: vec![],
+            capture: Default::default(),
+        };
+        let cer = options::Scenario {
+            capture: [("HOST".to_string(), "Origin-Host".to_string())].into(),
+            ..options.clone()
+        };
+        let global = Global::new(&options::Global { variables: vec![] })
+            .unwrap()
+            .with_captures(&[cer]);
+        let mut scenario = Scenario::new(&options, &global, Arc::clone(&dict), &enums()).unwrap();
+        let init = |host: &str| {
+            let value = Identity::new(host).into();
+            Arc::new(Captured::from([("HOST".to_string(), value)]))
+        };
+        let host = |request: &DiameterMessage| {
+            let avp = request.get_avp(293).unwrap();
+            avp.get_identity().unwrap().value().to_string()
+        };
+
+        // Each session uses the CEA of its connection
+        let mut session = Session::default();
+        session.set_init(init("peer1.example.com"));
+        assert_eq!(
+            host(&scenario.next_message(&mut session).unwrap()),
+            "peer1.example.com"
+        );
+        assert!(session.used_init());
+        session.set_init(init("peer2.example.com"));
+        assert_eq!(
+            host(&scenario.next_message(&mut session).unwrap()),
+            "peer2.example.com"
+        );
+
+        // Captured again by the session
+        let mut session = Session::default();
+        session.set_init(init("peer1.example.com"));
+        session
+            .captured
+            .insert("HOST".into(), Identity::new("ocs.example.com").into());
+        assert_eq!(
+            host(&scenario.next_message(&mut session).unwrap()),
+            "ocs.example.com"
+        );
+        assert!(!session.used_init());
+    }
+
+    #[test]
+    fn test_feed_variable() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(
+            &file,
+            "msisdn, imsi\n60123456789, 001010000000001\n60123456790, 001010000000002\n",
+        )
+        .unwrap();
+        let dict = Arc::new(Dictionary::new(&[&diameter::dictionary::DEFAULT_DICT_XML]));
+        let feed = |scope| options::Variable {
+            func: options::Function::CsvFeed,
+            scope,
+            file: Some(file.path().to_string_lossy().into()),
+            ..Default::default()
+        };
+        let global = Global::new(&options::Global {
+            variables: vec![[
+                ("SUB".into(), feed(options::Scope::Message)),
+                ("USER".into(), feed(options::Scope::Session)),
+            ]
+            .into()],
+        })
+        .unwrap();
+
+        let avp = |name: &str, value: &str| options::Avp {
+            name: name.into(),
+            value: options::Value::String(value.into()),
+            ..Default::default()
+        };
+        let options = options::Scenario {
+            name: "CCR".into(),
+            scenario_type: options::ScenarioType::Repeating,
+            times: 1,
+            message: options::Message {
+                command: "Credit-Control".into(),
+                application: "Charging Control".into(),
+                flags: Default::default(),
+                avps: vec![
+                    avp("Session-Id", "${USER.imsi};${SUB.imsi}"),
+                    avp("Subscription-Id-Data", "${SUB.msisdn}"),
+                ],
+            },
+            assertions: vec![],
+            capture: Default::default(),
+        };
+        let mut scenario = Scenario::new(&options, &global, Arc::clone(&dict), &enums()).unwrap();
+        let mut next = |session: &mut Session| {
+            let request = scenario.next_message(session).unwrap();
+            let value = |code| request.get_avp(code).unwrap().get_value().to_string();
+            format!("{} {}", value(263), value(444))
+        };
+
+        // Columns of a message from the same row, a row per session for USER
+        let mut session = Session::default();
+        assert_eq!(
+            next(&mut session),
+            "001010000000001;001010000000001 60123456789"
+        );
+        assert_eq!(
+            next(&mut session),
+            "001010000000001;001010000000002 60123456790"
+        );
+        let mut session = Session::default();
+        assert_eq!(
+            next(&mut session),
+            "001010000000002;001010000000001 60123456789"
+        );
+
+        let error = |source: &str| {
+            let source = options::Value::String(source.into());
+            Value::new(
+                &source,
+                &definition(AvpType::UTF8String),
+                &global,
+                Arc::clone(&dict),
+                &EnumDictionary::default(),
+            )
+            .err()
+            .unwrap()
+            .to_string()
+        };
+        assert_eq!(
+            error("${SUB}"),
+            "Feed 'SUB' is used by column, as ${SUB.column}"
+        );
+        assert_eq!(
+            error("${SUB.iccid}"),
+            "Column 'iccid' not found in feed 'SUB'"
+        );
+    }
+
+    #[test]
+    fn test_grouped_variable() {
+        let dict = Arc::new(Dictionary::new(&[&diameter::dictionary::DEFAULT_DICT_XML]));
+        let global = Global::new(&options::Global {
+            variables: vec![std::iter::once((
+                "COUNTER".into(),
+                options::Variable {
+                    func: options::Function::IncrementalCounter,
+                    min: 1,
+                    max: 100,
+                    step: 1,
+                    scope: options::Scope::Message,
+                    ..Default::default()
+                },
+            ))
+            .collect()],
+        })
+        .unwrap();
+        let avp = |name: &str, value: &str| options::Avp {
+            name: name.into(),
+            value: options::Value::String(value.into()),
+            ..Default::default()
+        };
+        let group = |name: &str, avps: Vec<options::Avp>| options::Avp {
+            name: name.into(),
+            value: options::Value::Avp(avps),
+            ..Default::default()
+        };
+        let options = options::Scenario {
+            name: "CCR-U".into(),
+            scenario_type: options::ScenarioType::Repeating,
+            times: 1,
+            message: options::Message {
+                command: "Credit-Control".into(),
+                application: "Charging Control".into(),
+                flags: Default::default(),
+                avps: vec![
+                    group(
+                        "Subscription-Id",
+                        vec![
+                            avp("Subscription-Id-Type", "1"),
+                            avp("Subscription-Id-Data", "601${COUNTER}"),
+                        ],
+                    ),
+                    group(
+                        "Multiple-Services-Credit-Control",
+                        vec![group(
+                            "Requested-Service-Unit",
+                            vec![avp("CC-Total-Octets", "${REQUEST_NUMBER}")],
+                        )],
+                    ),
+                    group("Granted-Service-Unit", vec![avp("CC-Total-Octets", "1024")]),
+                ],
+            },
+            assertions: vec![],
+            capture: Default::default(),
+        };
+        let mut scenario = Scenario::new(&options, &global, Arc::clone(&dict), &enums()).unwrap();
+        let paths: Vec<AvpPath> = [
+            "Subscription-Id.Subscription-Id-Data",
+            "Multiple-Services-Credit-Control.Requested-Service-Unit.CC-Total-Octets",
+            "Granted-Service-Unit.CC-Total-Octets",
+        ]
+        .iter()
+        .map(|path| AvpPath::new(path, &dict).unwrap())
+        .collect();
+        let mut next = |session: &mut Session| {
+            let request = scenario.next_message(session).unwrap();
+            let values: Vec<String> = paths
+                .iter()
+                .map(|path| avp_value_to_string(path.find(&request).unwrap()))
+                .collect();
+            values.join(" ")
+        };
+
+        let mut session = Session::default();
+        assert_eq!(next(&mut session), "6011 0 1024");
+        assert_eq!(next(&mut session), "6012 1 1024");
+        assert_eq!(next(&mut session), "6013 2 1024");
+
+        let error = Value::new(
+            &options::Value::Avp(vec![avp("Subscription-Id-Data", "${UNKNOWN}")]),
+            &definition(AvpType::Grouped),
+            &global,
+            Arc::clone(&dict),
+            &EnumDictionary::default(),
         )
+        .err()
         .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "AVP 'Subscription-Id-Data', error: Undefined variable 'UNKNOWN'"
+        );
+    }
+
+    #[test]
+    fn test_flags() {
+        let dict = Arc::new(Dictionary::new(&[&diameter::dictionary::DEFAULT_DICT_XML]));
+        let global = Global::new(&options::Global { variables: vec![] }).unwrap();
+        let avp = |name: &str, value: options::Value, flags: options::AvpFlags| options::Avp {
+            name: name.into(),
+            value,
+            flags,
+            ..Default::default()
+        };
+        let string = |value: &str| options::Value::String(value.into());
+        let options = options::Scenario {
+            name: "CCR".into(),
+            scenario_type: options::ScenarioType::Repeating,
+            times: 1,
+            message: options::Message {
+                command: "Credit-Control".into(),
+                application: "Charging Control".into(),
+                flags: options::MessageFlags::Named(options::NamedFlags {
+                    proxiable: true,
+                    retransmit: true,
+                    ..Default::default()
+                }),
+                avps: vec![
+                    avp("Session-Id", string("ses;1"), Default::default()),
+                    avp(
+                        "Subscription-Id",
+                        options::Value::Avp(vec![
+                            avp("Subscription-Id-Type", string("1"), Default::default()),
+                            avp(
+                                "Subscription-Id-Data",
+                                string("601"),
+                                options::AvpFlags {
+                                    mandatory: Some(false),
+                                    protected: Some(true),
+                                    ..Default::default()
+                                },
+                            ),
+                        ]),
+                        Default::default(),
+                    ),
+                    avp(
+                        "Service-Information",
+                        options::Value::Avp(vec![]),
+                        options::AvpFlags {
+                            vendor: Some(false),
+                            ..Default::default()
+                        },
+                    ),
+                ],
+            },
+            assertions: vec![],
+            capture: Default::default(),
+        };
+        let mut scenario = Scenario::new(&options, &global, Arc::clone(&dict), &enums()).unwrap();
+        let request = scenario.next_message(&mut Session::default()).unwrap();
+        assert_eq!(
+            request.get_flags(),
+            flags::REQUEST | flags::PROXYABLE | flags::RETRANSMIT
+        );
+        // Legacy integer, as given but for 0 which is a request
+        assert_eq!(
+            message_flags(&options::MessageFlags::Bits(0)),
+            flags::REQUEST
+        );
+        assert_eq!(
+            message_flags(&options::MessageFlags::Bits(
+                flags::REQUEST | flags::PROXYABLE
+            )),
+            flags::REQUEST | flags::PROXYABLE
+        );
+        assert_eq!(
+            message_flags(&options::MessageFlags::Bits(flags::PROXYABLE)),
+            flags::PROXYABLE
+        );
+
+        let bits = |avp: &diameter::avp::Avp| {
+            let flags = avp.get_flags();
+            (flags.vendor, flags.mandatory, flags.private)
+        };
+        assert_eq!(bits(request.get_avp(263).unwrap()), (false, true, false));
+        let subscription = request.get_avp(443).unwrap();
+        let AvpValue::Grouped(children) = subscription.get_value() else {
+            panic!("Subscription-Id is not grouped");
+        };
+        // Nested AVPs get the M-bit of the dictionary too
+        assert_eq!(bits(&children.avps()[0]), (false, true, false));
+        assert_eq!(bits(&children.avps()[1]), (false, false, true));
+        let service = request.get_avp(873).unwrap();
+        assert_eq!(service.get_vendor_id(), None);
+        assert_eq!(bits(service), (false, true, false));
+
+        let mut options = options;
+        options.message.avps[0].flags.vendor = Some(true);
+        assert_eq!(
+            Scenario::new(&options, &global, dict, &enums())
+                .err()
+                .unwrap()
+                .to_string(),
+            "AVP 'Session-Id', error: the V bit needs a Vendor-Id, set vendor_id"
+        );
+    }
+
+    #[test]
+    fn test_raw_avps() {
+        let dict = Arc::new(Dictionary::new(&[&diameter::dictionary::DEFAULT_DICT_XML]));
+        let global = Global::new(&options::Global { variables: vec![] }).unwrap();
+        let lua = mlua::Lua::new();
+        let avps = lua
+            .load(
+                r#"{
+                    { name = "SGSN-Address", code = 1228, vendor_id = 10415, type = "Address",
+                      value = "127.0.0.1", flags = { mandatory = true } },
+                    { code = 99999, value = "hex:00ff" },
+                    { name = "CC-Request-Number", type = "OctetString", value = "hex:0001" },
+                    { code = 99998, vendor_id = 12345, type = "Grouped",
+                      value = { { code = 99997, type = "Unsigned32", value = "7" } } },
+                }"#,
+            )
+            .eval()
+            .unwrap();
+        let options = options::Scenario {
+            name: "CCR".into(),
+            scenario_type: options::ScenarioType::Repeating,
+            times: 1,
+            message: options::Message {
+                command: "Credit-Control".into(),
+                application: "Charging Control".into(),
+                flags: Default::default(),
+                avps: mlua::LuaSerdeExt::from_value(&lua, avps).unwrap(),
+            },
+            assertions: vec![],
+            capture: Default::default(),
+        };
+        let mut scenario = Scenario::new(&options, &global, Arc::clone(&dict), &enums()).unwrap();
+        let request = scenario.next_message(&mut Session::default()).unwrap();
+
+        let sgsn = request.get_avp(1228).unwrap();
+        assert_eq!(sgsn.get_vendor_id(), Some(10415));
+        assert!(sgsn.get_flags().vendor && sgsn.get_flags().mandatory);
+        assert_eq!(sgsn.get_value().to_string(), "127.0.0.1");
+        let raw = request.get_avp(99999).unwrap();
+        assert_eq!(raw.get_vendor_id(), None);
+        assert!(!raw.get_flags().mandatory);
+        assert_eq!(raw.get_value().to_string(), "00 ff");
+        // Two bytes where the dictionary expects an Unsigned32
+        assert_eq!(request.get_avp(415).unwrap().get_length(), 10);
+        let AvpValue::Grouped(group) = request.get_avp(99998).unwrap().get_value() else {
+            panic!("AVP 99998 is not grouped");
+        };
+        assert_eq!(group.avps()[0].get_code(), 99997);
+        assert_eq!(group.avps()[0].get_value().to_string(), "7");
+        // Unknown AVPs are still rendered
+        assert!(request.to_string().contains("00 ff"));
+
+        let error = |avp: options::Avp| {
+            let definition = avp_definition(&avp, &dict).err().unwrap();
+            format!("{}: {}", avp_label(&avp), definition)
+        };
+        assert_eq!(error(options::Avp::default()), ": needs a name or a code");
+        assert_eq!(
+            error(options::Avp {
+                code: Some(99999),
+                avp_type: Some("Unsigned".into()),
+                ..Default::default()
+            }),
+            "99999: unknown type 'Unsigned'"
+        );
+        assert_eq!(
+            error(options::Avp {
+                name: "SGSN-Address".into(),
+                ..Default::default()
+            }),
+            "SGSN-Address: not found in dictionary"
+        );
+    }
+
+    #[test]
+    fn test_avp_value_round_trip() {
+        let types = [
+            "Address",
+            "IPv4",
+            "IPv6",
+            "DiameterIdentity",
+            "DiameterURI",
+            "Enumerated",
+            "Float32",
+            "Float64",
+            "Integer32",
+            "Integer64",
+            "OctetString",
+            "Unsigned32",
+            "Unsigned64",
+            "UTF8String",
+            "Time",
+        ];
+        let avps: Vec<String> = types
+            .iter()
+            .enumerate()
+            .map(|(i, avp_type)| {
+                let items = if *avp_type == "Enumerated" {
+                    r#"<item code="-2" name="MINUS_TWO"/><item code="5" name="FIVE"/>"#
+                } else {
+                    ""
+                };
+                format!(
+                    r#"<avp name="Test-{}" code="{}" vendor-id="99902"><data type="{}">{}</data></avp>"#,
+                    avp_type,
+                    2910 + i,
+                    avp_type,
+                    items
+                )
+            })
+            .collect();
+        let xml = format!(
+            r#"<diameter><application id="4" name="Test">{}</application></diameter>"#,
+            avps.concat()
+        );
+        let mut dict = Dictionary::new(&[]);
+        dict.load_xml(&xml);
+        let mut enums = EnumDictionary::new(&[]);
+        enums.load_xml(&xml).unwrap();
+        let dict = Arc::new(dict);
 
-        assert_eq!("ses;0_1", variable.compute());
-        assert_eq!("ses;1_4", variable.compute());
-        assert_eq!("ses;2_1", variable.compute());
+        // Encoded and decoded again, with the bytes of the value
+        let round_trip = |avp_type: &str, value: &str| -> Result<(String, String), String> {
+            let definition = dict.get_avp_by_name(&format!("Test-{}", avp_type)).unwrap();
+            let items = enums.get(definition.code, definition.vendor_id);
+            let value = named_avp_value(value, definition.avp_type, items.as_deref())
+                .map_err(|e| e.to_string())?;
+            let avp = diameter::avp::Avp::new(
+                definition.code,
+                definition.vendor_id,
+                0,
+                value,
+                Arc::clone(&dict),
+            );
+            let mut encoded = vec![];
+            avp.encode_to(&mut encoded).unwrap();
+            let length = u32::from_be_bytes([0, encoded[5], encoded[6], encoded[7]]) as usize;
+            let bytes: Vec<String> = encoded[12..length]
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect();
+            let decoded = diameter::avp::Avp::decode_from(
+                &mut std::io::Cursor::new(&encoded),
+                Arc::clone(&dict),
+            )
+            .unwrap();
+            assert_eq!(value_type(decoded.get_value()), definition.avp_type);
+            Ok((avp_value_to_string(decoded.get_value()), bytes.concat()))
+        };
+        let ok = |avp_type: &str, value: &str| round_trip(avp_type, value).unwrap();
+
+        assert_eq!(
+            ok("Address", "10.0.0.1"),
+            ("10.0.0.1".into(), "00010a000001".into())
+        );
+        assert_eq!(
+            ok("IPv4", "10.0.0.1"),
+            ("10.0.0.1".into(), "0a000001".into())
+        );
+        assert_eq!(ok("IPv6", "::1").0, "::1");
+        assert_eq!(
+            ok("DiameterIdentity", "host.example.com").0,
+            "host.example.com"
+        );
+        assert_eq!(
+            ok("DiameterURI", "aaa://host.example.com").0,
+            "aaa://host.example.com"
+        );
+        assert_eq!(ok("Enumerated", "5"), ("5".into(), "00000005".into()));
+        assert_eq!(
+            ok("Enumerated", "MINUS_TWO"),
+            ("-2".into(), "fffffffe".into())
+        );
+        assert_eq!(ok("Float32", "1.5"), ("1.5".into(), "3fc00000".into()));
+        assert_eq!(ok("Float32", "-0.25"), ("-0.25".into(), "be800000".into()));
+        assert_eq!(
+            ok("Float64", "1.5"),
+            ("1.5".into(), "3ff8000000000000".into())
+        );
+        assert_eq!(ok("Integer32", "-1"), ("-1".into(), "ffffffff".into()));
+        assert_eq!(
+            ok("Integer32", "2147483647"),
+            ("2147483647".into(), "7fffffff".into())
+        );
+        assert_eq!(
+            ok("Integer64", "-2"),
+            ("-2".into(), "fffffffffffffffe".into())
+        );
+        assert_eq!(
+            ok("Unsigned32", "4294967295"),
+            ("4294967295".into(), "ffffffff".into())
+        );
+        assert_eq!(
+            ok("Unsigned64", "18446744073709551615"),
+            ("18446744073709551615".into(), "ffffffffffffffff".into())
+        );
+        assert_eq!(
+            ok("UTF8String", "héllo"),
+            ("héllo".into(), "68c3a96c6c6f".into())
+        );
+        assert_eq!(
+            ok("Time", "2020-01-01T00:00:00Z").0,
+            "2020-01-01T00:00:00+00:00"
+        );
+        assert_eq!(ok("OctetString", "abc"), ("abc".into(), "616263".into()));
+        assert_eq!(
+            ok("OctetString", "plain:hex:1"),
+            ("hex:1".into(), "6865783a31".into())
+        );
+        assert_eq!(ok("OctetString", "hex:ff 00 0A").1, "ff000a");
+        assert_eq!(ok("OctetString", "base64:/wAK").1, "ff000a");
+
+        let error = |avp_type: &str, value: &str| round_trip(avp_type, value).err().unwrap();
+        assert_eq!(error("Enumerated", "SIX"), "unknown Enumerated value 'SIX'");
+        assert_eq!(
+            error("Integer32", "2147483648"),
+            "number too large to fit in target type"
+        );
+        assert_eq!(error("Unsigned32", "-1"), "invalid digit found in string");
+        assert_eq!(
+            error("OctetString", "hex:f"),
+            "odd number of hex digits in 'hex:f'"
+        );
+        assert_eq!(
+            error("OctetString", "hex:zz"),
+            "invalid hex digits in 'hex:zz'"
+        );
+        assert!(error("OctetString", "base64:*").starts_with("Invalid"));
+    }
+
+    #[test]
+    fn test_undefined_variable() {
+        let dict = Arc::new(Dictionary::new(&[]));
+        let global = Global::new(&options::Global { variables: vec![] }).unwrap();
+
+        assert_eq!(variable_names("${A}-${B}"), vec!["A", "B"]);
+        let result = Value::new(
+            &options::Value::String("ses;${COUNTER}".into()),
+            &definition(AvpType::UTF8String),
+            &global,
+            dict,
+            &EnumDictionary::default(),
+        );
+        assert_eq!(
+            result.err().unwrap().to_string(),
+            "Undefined variable 'COUNTER'"
+        );
     }
 }
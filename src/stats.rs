@@ -0,0 +1,510 @@
+use diameter::DiameterMessage;
+use hdrhistogram::Histogram;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::time::Duration;
+
+const RESULT_CODE: u32 = 268;
+const EXPERIMENTAL_RESULT: u32 = 297;
+const EXPERIMENTAL_RESULT_CODE: u32 = 298;
+
+/// Highest latency tracked, anything slower is recorded as this value.
+const MAX_LATENCY_US: u64 = 3_600_000_000;
+
+/// Send-to-answer latencies, recorded in microseconds with 3 significant digits.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Latency {
+    histogram: Histogram<u64>,
+}
+
+impl Default for Latency {
+    fn default() -> Self {
+        Latency {
+            histogram: Histogram::new_with_bounds(1, MAX_LATENCY_US, 3).unwrap(),
+        }
+    }
+}
+
+impl Latency {
+    pub fn record(&mut self, latency: Duration) {
+        let us = (latency.as_micros() as u64).clamp(1, MAX_LATENCY_US);
+        self.histogram.saturating_record(us);
+    }
+
+    pub fn merge(&mut self, other: &Latency) {
+        self.histogram.add(&other.histogram).unwrap();
+    }
+
+    pub fn count(&self) -> u64 {
+        self.histogram.len()
+    }
+
+    pub fn min(&self) -> Duration {
+        Duration::from_micros(self.histogram.min())
+    }
+
+    pub fn mean(&self) -> Duration {
+        Duration::from_micros(self.histogram.mean().round() as u64)
+    }
+
+    pub fn max(&self) -> Duration {
+        Duration::from_micros(self.histogram.max())
+    }
+
+    /// `percentile` ranges from 0 to 100, e.g. 99.9
+    pub fn percentile(&self, percentile: f64) -> Duration {
+        Duration::from_micros(self.histogram.value_at_percentile(percentile))
+    }
+}
+
+impl fmt::Display for Latency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.count() == 0 {
+            return write!(f, "no samples");
+        }
+        write!(
+            f,
+            "min {:?}, mean {:?}, p50 {:?}, p90 {:?}, p99 {:?}, p99.9 {:?}, max {:?}",
+            self.min(),
+            self.mean(),
+            self.percentile(50.0),
+            self.percentile(90.0),
+            self.percentile(99.0),
+            self.percentile(99.9),
+            self.max()
+        )
+    }
+}
+
+/// Result-Code classes from RFC 6733 section 7.1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ResultClass {
+    Informational,
+    Success,
+    ProtocolError,
+    TransientFailure,
+    PermanentFailure,
+    Unknown,
+}
+
+impl ResultClass {
+    pub fn of(code: u32) -> ResultClass {
+        match code / 1000 {
+            1 => ResultClass::Informational,
+            2 => ResultClass::Success,
+            3 => ResultClass::ProtocolError,
+            4 => ResultClass::TransientFailure,
+            5 => ResultClass::PermanentFailure,
+            _ => ResultClass::Unknown,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ResultClass::Informational => "1xxx",
+            ResultClass::Success => "2xxx",
+            ResultClass::ProtocolError => "3xxx",
+            ResultClass::TransientFailure => "4xxx",
+            ResultClass::PermanentFailure => "5xxx",
+            ResultClass::Unknown => "unknown",
+        }
+    }
+}
+
+/// Result-Code of an answer, or the Experimental-Result-Code when there is none.
+pub fn result_code(msg: &DiameterMessage) -> Option<u32> {
+    if let Some(avp) = msg.get_avp(RESULT_CODE) {
+        return avp.get_unsigned32();
+    }
+    msg.get_avp(EXPERIMENTAL_RESULT)?
+        .get_grouped()?
+        .avps()
+        .iter()
+        .find(|avp| avp.get_code() == EXPERIMENTAL_RESULT_CODE)?
+        .get_unsigned32()
+}
+
+/// Whether an answer counts as successful, `success_codes` empty means any 2xxx.
+pub fn is_success(code: Option<u32>, success_codes: &[u32]) -> bool {
+    match code {
+        Some(code) if success_codes.is_empty() => ResultClass::of(code) == ResultClass::Success,
+        Some(code) => success_codes.contains(&code),
+        None => false,
+    }
+}
+
+/// Tally of the result codes of answers.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ResultCodes {
+    pub codes: BTreeMap<u32, u64>,
+    pub missing: u64,
+    pub failures: u64,
+}
+
+impl ResultCodes {
+    pub fn record(&mut self, code: Option<u32>, success: bool) {
+        match code {
+            Some(code) => *self.codes.entry(code).or_default() += 1,
+            None => self.missing += 1,
+        }
+        if !success {
+            self.failures += 1;
+        }
+    }
+
+    pub fn merge(&mut self, other: &ResultCodes) {
+        for (code, count) in &other.codes {
+            *self.codes.entry(*code).or_default() += count;
+        }
+        self.missing += other.missing;
+        self.failures += other.failures;
+    }
+
+    pub fn by_class(&self) -> BTreeMap<ResultClass, u64> {
+        let mut classes = BTreeMap::new();
+        for (code, count) in &self.codes {
+            *classes.entry(ResultClass::of(*code)).or_default() += count;
+        }
+        classes
+    }
+}
+
+impl fmt::Display for ResultCodes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let classes = self.by_class();
+        for class in [
+            ResultClass::Success,
+            ResultClass::ProtocolError,
+            ResultClass::TransientFailure,
+            ResultClass::PermanentFailure,
+        ] {
+            write!(
+                f,
+                "{} {}, ",
+                class.name(),
+                classes.get(&class).unwrap_or(&0)
+            )?;
+        }
+        for class in [ResultClass::Informational, ResultClass::Unknown] {
+            if let Some(count) = classes.get(&class) {
+                write!(f, "{} {}, ", class.name(), count)?;
+            }
+        }
+        write!(
+            f,
+            "missing {}, {} unsuccessful",
+            self.missing, self.failures
+        )?;
+
+        if !self.codes.is_empty() {
+            let codes: Vec<String> = self
+                .codes
+                .iter()
+                .map(|(code, count)| format!("{}={}", code, count))
+                .collect();
+            write!(f, " ({})", codes.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Counters of a runner, read by the live reporter while the run is going on.
+#[derive(Debug, Default)]
+pub struct LiveStats {
+    requests: AtomicU64,
+    answers: AtomicU64,
+    failures: AtomicU64,
+    errors: AtomicU64,
+    /// Errors of requests which were sent, the others never were outstanding
+    send_errors: AtomicU64,
+    timeouts: AtomicU64,
+    window: Mutex<Latency>,
+}
+
+impl LiveStats {
+    pub fn request_sent(&self) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn answered(&self, latency: Duration, success: bool) {
+        self.answers.fetch_add(1, Ordering::Relaxed);
+        if !success {
+            self.failures.fetch_add(1, Ordering::Relaxed);
+        }
+        self.window.lock().unwrap().record(latency);
+    }
+
+    pub fn timed_out(&self) {
+        self.timeouts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A request sent got no answer, but an error.
+    pub fn failed(&self) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+        self.send_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A request could not be sent, e.g. built.
+    pub fn not_sent(&self) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Totals so far, with the latencies recorded since the previous snapshot.
+    fn snapshot(&self) -> LiveSnapshot {
+        LiveSnapshot {
+            requests: self.requests.load(Ordering::Relaxed),
+            answers: self.answers.load(Ordering::Relaxed),
+            failures: self.failures.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            send_errors: self.send_errors.load(Ordering::Relaxed),
+            timeouts: self.timeouts.load(Ordering::Relaxed),
+            latency: std::mem::take(&mut *self.window.lock().unwrap()),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct LiveSnapshot {
+    requests: u64,
+    answers: u64,
+    failures: u64,
+    errors: u64,
+    send_errors: u64,
+    timeouts: u64,
+    latency: Latency,
+}
+
+/// One line of live statistics, covering the interval since the previous one.
+#[derive(Debug)]
+pub struct LiveLine {
+    /// Time since the reporter started
+    pub elapsed: Duration,
+    pub tps: f64,
+    pub outstanding: u64,
+    pub failures: u64,
+    pub errors: u64,
+    pub timeouts: u64,
+    pub latency: Latency,
+}
+
+impl fmt::Display for LiveLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:.1} TPS, {} outstanding, {} unsuccessful, {} errors, {} timeouts",
+            self.tps, self.outstanding, self.failures, self.errors, self.timeouts
+        )?;
+        if self.latency.count() > 0 {
+            write!(
+                f,
+                ", latency p50 {:?}, p90 {:?}, p99 {:?}, max {:?}",
+                self.latency.percentile(50.0),
+                self.latency.percentile(90.0),
+                self.latency.percentile(99.0),
+                self.latency.max()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Aggregates the live counters of all runners.
+pub struct LiveReporter {
+    runners: Vec<Arc<LiveStats>>,
+    answers: u64,
+    failures: u64,
+    errors: u64,
+    timeouts: u64,
+    start: Instant,
+    last: Instant,
+}
+
+impl LiveReporter {
+    pub fn new(runners: Vec<Arc<LiveStats>>) -> Self {
+        LiveReporter {
+            runners,
+            answers: 0,
+            failures: 0,
+            errors: 0,
+            timeouts: 0,
+            start: Instant::now(),
+            last: Instant::now(),
+        }
+    }
+
+    pub fn tick(&mut self) -> LiveLine {
+        let mut total = LiveSnapshot::default();
+        for runner in &self.runners {
+            let snapshot = runner.snapshot();
+            total.requests += snapshot.requests;
+            total.answers += snapshot.answers;
+            total.failures += snapshot.failures;
+            total.errors += snapshot.errors;
+            total.send_errors += snapshot.send_errors;
+            total.timeouts += snapshot.timeouts;
+            total.latency.merge(&snapshot.latency);
+        }
+
+        let line = LiveLine {
+            elapsed: self.start.elapsed(),
+            tps: (total.answers - self.answers) as f64 / self.last.elapsed().as_secs_f64(),
+            // The counters of a runner may move while read, an answer seen before its
+            // request
+            outstanding: total
+                .requests
+                .saturating_sub(total.answers + total.send_errors + total.timeouts),
+            failures: total.failures - self.failures,
+            errors: total.errors - self.errors,
+            timeouts: total.timeouts - self.timeouts,
+            latency: total.latency,
+        };
+
+        self.answers = total.answers;
+        self.failures = total.failures;
+        self.errors = total.errors;
+        self.timeouts = total.timeouts;
+        self.last = Instant::now();
+        line
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diameter::avp::flags::M;
+    use diameter::avp::{Grouped, Unsigned32};
+    use diameter::dictionary::{self, Dictionary};
+    use diameter::{ApplicationId, CommandCode};
+
+    #[test]
+    fn test_latency_percentiles() {
+        let mut latency = Latency::default();
+        for ms in 1..=1000 {
+            latency.record(Duration::from_millis(ms));
+        }
+        assert_eq!(latency.count(), 1000);
+        assert_eq!(latency.min(), Duration::from_millis(1));
+        assert_eq!(latency.max(), Duration::from_micros(1000447));
+        assert_eq!(latency.percentile(50.0), Duration::from_micros(500223));
+        assert_eq!(latency.percentile(99.0), Duration::from_micros(990207));
+        assert_eq!(latency.mean().as_millis(), 500);
+    }
+
+    #[test]
+    fn test_latency_merge() {
+        let mut a = Latency::default();
+        let mut b = Latency::default();
+        a.record(Duration::from_micros(100));
+        b.record(Duration::from_micros(300));
+        b.record(Duration::from_secs(7200));
+        a.merge(&b);
+        assert_eq!(a.count(), 3);
+        assert_eq!(a.min(), Duration::from_micros(100));
+        assert_eq!(a.percentile(50.0), Duration::from_micros(300));
+        assert!(a.max() >= Duration::from_secs(3600));
+        assert_eq!(Latency::default().to_string(), "no samples");
+    }
+
+    fn answer(dict: Arc<Dictionary>) -> DiameterMessage {
+        DiameterMessage::new(
+            CommandCode::CreditControl,
+            ApplicationId::CreditControl,
+            0,
+            1,
+            1,
+            dict,
+        )
+    }
+
+    #[test]
+    fn test_result_code() {
+        let dict = Arc::new(Dictionary::new(&[&dictionary::DEFAULT_DICT_XML]));
+
+        let mut msg = answer(Arc::clone(&dict));
+        assert_eq!(result_code(&msg), None);
+        msg.add_avp(RESULT_CODE, None, M, Unsigned32::new(5030).into());
+        assert_eq!(result_code(&msg), Some(5030));
+
+        let mut msg = answer(Arc::clone(&dict));
+        let mut experimental = Grouped::new(vec![], Arc::clone(&dict));
+        experimental.add_avp(266, None, M, Unsigned32::new(10415).into());
+        experimental.add_avp(
+            EXPERIMENTAL_RESULT_CODE,
+            None,
+            M,
+            Unsigned32::new(4181).into(),
+        );
+        msg.add_avp(EXPERIMENTAL_RESULT, None, M, experimental.into());
+        assert_eq!(result_code(&msg), Some(4181));
+    }
+
+    #[test]
+    fn test_result_codes() {
+        let mut results = ResultCodes::default();
+        for code in [
+            Some(2001),
+            Some(2001),
+            Some(2002),
+            Some(4012),
+            Some(5030),
+            None,
+        ] {
+            results.record(code, is_success(code, &[2001]));
+        }
+        assert_eq!(results.failures, 4);
+        assert_eq!(results.missing, 1);
+        assert!(is_success(Some(2002), &[]));
+        assert!(!is_success(Some(3002), &[]));
+
+        let mut total = ResultCodes::default();
+        total.record(Some(2001), true);
+        total.merge(&results);
+        assert_eq!(total.codes.get(&2001), Some(&3));
+        assert_eq!(total.by_class().get(&ResultClass::Success), Some(&4));
+        assert_eq!(
+            total.to_string(),
+            "2xxx 4, 3xxx 0, 4xxx 1, 5xxx 1, missing 1, 4 unsuccessful \
+             (2001=3, 2002=1, 4012=1, 5030=1)"
+        );
+    }
+
+    #[test]
+    fn test_live_reporter() {
+        let runners = vec![
+            Arc::new(LiveStats::default()),
+            Arc::new(LiveStats::default()),
+        ];
+        let mut reporter = LiveReporter::new(runners.clone());
+
+        for _ in 0..4 {
+            runners[0].request_sent();
+            runners[1].request_sent();
+        }
+        runners[0].answered(Duration::from_millis(2), true);
+        runners[1].answered(Duration::from_millis(4), false);
+        runners[1].timed_out();
+        runners[1].failed();
+        // Never sent, so never outstanding
+        runners[0].not_sent();
+
+        let line = reporter.tick();
+        assert!(line.tps > 0.0);
+        assert_eq!(line.outstanding, 4);
+        assert_eq!(line.failures, 1);
+        assert_eq!(line.errors, 2);
+        assert_eq!(line.timeouts, 1);
+        assert_eq!(line.latency.count(), 2);
+        assert_eq!(line.latency.min(), Duration::from_millis(2));
+
+        // Counts are per interval, latencies are rolling
+        runners[0].answered(Duration::from_millis(8), true);
+        let line = reporter.tick();
+        assert_eq!(line.outstanding, 3);
+        assert_eq!((line.failures, line.errors, line.timeouts), (0, 0, 0));
+        assert_eq!(line.latency.count(), 1);
+        assert!(line.to_string().contains("3 outstanding"));
+    }
+}
@@ -0,0 +1,800 @@
+//! Client side Diameter transport over TCP or TLS.
+//!
+//! `diameter::transport::DiameterClient` only exposes an on/off switch for TLS,
+//! so connections are established here, reusing the crate's `Codec` for framing.
+use crate::identifiers::Identifiers;
+use crate::options;
+use diameter::avp::flags::M;
+use diameter::avp::{AvpValue, Enumerated, Identity, Unsigned32};
+use diameter::dictionary::Dictionary;
+use diameter::transport::client::ResponseFuture;
+use diameter::transport::Codec;
+use diameter::{flags, CommandCode, DiameterMessage, Error, Result};
+use std::collections::HashMap;
+use std::ops::DerefMut;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::oneshot;
+use tokio::sync::oneshot::Sender;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+
+pub const RESULT_CODE: u32 = 268;
+pub const DIAMETER_SUCCESS: u32 = 2001;
+
+const ORIGIN_HOST: u32 = 264;
+const ORIGIN_REALM: u32 = 296;
+const DISCONNECT_CAUSE: u32 = 273;
+
+type Writer = Arc<Mutex<Box<dyn AsyncWrite + Send + Unpin>>>;
+type Pending = Arc<Mutex<HashMap<u32, Sender<DiameterMessage>>>>;
+
+/// A connection to a single Diameter peer.
+///
+/// Requests are matched with their answers by Hop-by-Hop Identifier, allocated by the
+/// connection. Clones share the same underlying connection.
+#[derive(Clone)]
+pub struct Connection {
+    writer: Writer,
+    pending: Pending,
+    state: Arc<ConnectionState>,
+}
+
+/// The read half of a `Connection`, driven by `Connection::handle`.
+pub struct ConnectionHandler {
+    address: String,
+    reader: Box<dyn AsyncRead + Send + Unpin>,
+    writer: Writer,
+    pending: Pending,
+    state: Arc<ConnectionState>,
+}
+
+struct ConnectionState {
+    last_received: std::sync::Mutex<Instant>,
+    watchdog_answered: AtomicU64,
+    down: AtomicBool,
+    closing: AtomicBool,
+    /// No message can be received any more
+    closed: AtomicBool,
+    identifiers: std::sync::Mutex<Identifiers>,
+}
+
+/// Origin-Host and Origin-Realm this side of the connection identifies itself with.
+#[derive(Debug, Clone)]
+pub struct Origin {
+    pub host: String,
+    pub realm: String,
+}
+
+impl Origin {
+    /// Takes the origin from the CER sent on the connection.
+    pub fn from_message(msg: &DiameterMessage) -> Option<Origin> {
+        Some(Origin {
+            host: identity_value(msg, ORIGIN_HOST)?,
+            realm: identity_value(msg, ORIGIN_REALM)?,
+        })
+    }
+
+    /// Appends Origin-Host and Origin-Realm to a message.
+    pub fn add_to(&self, msg: &mut DiameterMessage) {
+        msg.add_avp(ORIGIN_HOST, None, M, Identity::new(&self.host).into());
+        msg.add_avp(ORIGIN_REALM, None, M, Identity::new(&self.realm).into());
+    }
+}
+
+fn identity_value(msg: &DiameterMessage, code: u32) -> Option<String> {
+    match msg.get_avp(code)?.get_value() {
+        AvpValue::Identity(value) => Some(value.value().into()),
+        AvpValue::UTF8String(value) => Some(value.value().into()),
+        _ => None,
+    }
+}
+
+/// Builds an answer to `req` carrying the given Result-Code.
+pub fn answer(
+    req: &DiameterMessage,
+    result_code: u32,
+    origin: &Origin,
+    dict: Arc<Dictionary>,
+) -> DiameterMessage {
+    let mut res = DiameterMessage::new(
+        req.get_command_code(),
+        req.get_application_id(),
+        req.get_flags() & !flags::REQUEST,
+        req.get_hop_by_hop_id(),
+        req.get_end_to_end_id(),
+        dict,
+    );
+    res.add_avp(RESULT_CODE, None, M, Unsigned32::new(result_code).into());
+    origin.add_to(&mut res);
+    res
+}
+
+impl Connection {
+    /// Connects to the peer, performing the TLS handshake when enabled.
+    pub async fn connect(
+        peer: &options::Peer,
+        tls: &options::Tls,
+        identifiers: Identifiers,
+    ) -> Result<(Connection, ConnectionHandler)> {
+        let address = peer.address();
+        let stream = TcpStream::connect(&address).await?;
+
+        if tls.enable {
+            let connector = tls_connector(tls)?;
+            let domain = tls.sni.as_deref().unwrap_or(&peer.host);
+            let stream = connector.connect(domain, stream).await?;
+            Ok(Self::from_stream(&address, stream, identifiers))
+        } else {
+            Ok(Self::from_stream(&address, stream, identifiers))
+        }
+    }
+
+    /// A connection over an established stream to the peer at `address`.
+    pub fn from_stream<S>(
+        address: &str,
+        stream: S,
+        identifiers: Identifiers,
+    ) -> (Connection, ConnectionHandler)
+    where
+        S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    {
+        let (reader, writer) = tokio::io::split(stream);
+        let writer: Writer = Arc::new(Mutex::new(Box::new(writer)));
+        let pending = Arc::new(Mutex::new(HashMap::new()));
+        let state = Arc::new(ConnectionState {
+            last_received: std::sync::Mutex::new(Instant::now()),
+            watchdog_answered: AtomicU64::new(0),
+            down: AtomicBool::new(false),
+            closing: AtomicBool::new(false),
+            closed: AtomicBool::new(false),
+            identifiers: std::sync::Mutex::new(identifiers),
+        });
+        let connection = Connection {
+            writer: Arc::clone(&writer),
+            pending: Arc::clone(&pending),
+            state: Arc::clone(&state),
+        };
+        let handler = ConnectionHandler {
+            address: address.into(),
+            reader: Box::new(reader),
+            writer,
+            pending,
+            state,
+        };
+        (connection, handler)
+    }
+
+    /// Reads messages from the peer and hands answers to the pending requests.
+    ///
+    /// Peer-initiated DWRs and DPRs are answered from `origin`.
+    /// Returns when the connection is closed or a message cannot be decoded, the requests
+    /// still awaiting their answer then fail at once.
+    pub async fn handle(handler: &mut ConnectionHandler, dict: Arc<Dictionary>, origin: Origin) {
+        loop {
+            let msg = match Codec::decode(&mut handler.reader, Arc::clone(&dict)).await {
+                Ok(msg) => msg,
+                Err(Error::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                    log::debug!("[{}] Connection closed by peer", handler.address);
+                    break;
+                }
+                Err(e) => {
+                    log::error!("[{}] Failed to read message; error: {}", handler.address, e);
+                    break;
+                }
+            };
+            *handler.state.last_received.lock().unwrap() = Instant::now();
+
+            if msg.get_flags() & flags::REQUEST != 0 {
+                let result = Self::process_request(
+                    &handler.address,
+                    &handler.writer,
+                    &handler.state,
+                    msg,
+                    &origin,
+                    &dict,
+                )
+                .await;
+                if let Err(e) = result {
+                    log::error!(
+                        "[{}] Failed to answer request; error: {}",
+                        handler.address,
+                        e
+                    );
+                    break;
+                }
+                continue;
+            }
+
+            let hop_by_hop = msg.get_hop_by_hop_id();
+            let sender = handler.pending.lock().await.remove(&hop_by_hop);
+            match sender {
+                Some(sender) => {
+                    // The receiver is gone if the request was abandoned
+                    let _ = sender.send(msg);
+                }
+                None => {
+                    log::warn!(
+                        "[{}] No request found for hop_by_hop_id {}",
+                        handler.address,
+                        hop_by_hop
+                    );
+                }
+            }
+        }
+
+        // Dropping the senders fails the requests awaiting an answer
+        handler.state.closed.store(true, Ordering::Relaxed);
+        handler.pending.lock().await.clear();
+    }
+
+    async fn process_request(
+        address: &str,
+        writer: &Writer,
+        state: &ConnectionState,
+        req: DiameterMessage,
+        origin: &Origin,
+        dict: &Arc<Dictionary>,
+    ) -> Result<()> {
+        match req.get_command_code() {
+            CommandCode::DeviceWatchdog => {
+                log::debug!("[{}] Answering DWR", address);
+                let dwa = answer(&req, DIAMETER_SUCCESS, origin, Arc::clone(dict));
+                state.watchdog_answered.fetch_add(1, Ordering::Relaxed);
+                let mut writer = writer.lock().await;
+                Codec::encode(&mut writer.deref_mut(), &dwa).await?;
+            }
+            CommandCode::DisconnectPeer => {
+                // RFC 6733 5.4, no new requests once DPR is received, the connection is
+                // closed after DPA
+                log::info!("[{}] Peer requested disconnection", address);
+                state.closing.store(true, Ordering::Relaxed);
+                let dpa = answer(&req, DIAMETER_SUCCESS, origin, Arc::clone(dict));
+                let mut writer = writer.lock().await;
+                Codec::encode(&mut writer.deref_mut(), &dpa).await?;
+                writer.shutdown().await?;
+            }
+            code => {
+                log::warn!("[{}] Ignoring unsupported request {}", address, code);
+            }
+        }
+        Ok(())
+    }
+
+    /// Identifiers of the next request, skipping Hop-by-Hop Identifiers still awaiting
+    /// their answer.
+    pub async fn next_identifiers(&self) -> (u32, u32) {
+        let pending = self.pending.lock().await;
+        let mut identifiers = self.state.identifiers.lock().unwrap();
+        loop {
+            let next = identifiers.next();
+            if !pending.contains_key(&next.0) {
+                return next;
+            }
+        }
+    }
+
+    /// Sends a request and returns a future resolving to its answer.
+    ///
+    /// Fails if a request with the same Hop-by-Hop Identifier is still awaiting its answer.
+    pub async fn send_message(&self, req: DiameterMessage) -> Result<ResponseFuture> {
+        let (tx, rx) = oneshot::channel();
+        let hop_by_hop = req.get_hop_by_hop_id();
+        {
+            let mut pending = self.pending.lock().await;
+            if self.is_closed() {
+                return Err(Error::ClientError("Connection is closed".into()));
+            }
+            if pending.contains_key(&hop_by_hop) {
+                return Err(Error::ClientError(format!(
+                    "Hop-by-Hop Identifier {} is already awaiting an answer",
+                    hop_by_hop
+                )));
+            }
+            pending.insert(hop_by_hop, tx);
+        }
+
+        let mut writer = self.writer.lock().await;
+        if let Err(e) = Codec::encode(&mut writer.deref_mut(), &req).await {
+            self.pending.lock().await.remove(&hop_by_hop);
+            return Err(e);
+        }
+        Ok(ResponseFuture { receiver: rx })
+    }
+
+    /// Sends DPR, the returned future resolves to the DPA.
+    pub async fn disconnect_peer(
+        &self,
+        origin: &Origin,
+        cause: options::DisconnectCause,
+        dict: Arc<Dictionary>,
+    ) -> Result<ResponseFuture> {
+        let (hop_by_hop, end_to_end) = self.next_identifiers().await;
+        let mut dpr = DiameterMessage::new(
+            CommandCode::DisconnectPeer,
+            diameter::ApplicationId::Common,
+            flags::REQUEST,
+            hop_by_hop,
+            end_to_end,
+            dict,
+        );
+        origin.add_to(&mut dpr);
+        dpr.add_avp(
+            DISCONNECT_CAUSE,
+            None,
+            M,
+            Enumerated::new(cause.code()).into(),
+        );
+        self.send_message(dpr).await
+    }
+
+    /// Closes the write side of the connection.
+    pub async fn close(&self) {
+        let _ = self.writer.lock().await.shutdown().await;
+    }
+
+    /// Forgets a request whose answer is no longer awaited.
+    pub async fn abandon(&self, hop_by_hop: u32) {
+        self.pending.lock().await.remove(&hop_by_hop);
+    }
+
+    /// When the last message was received from the peer.
+    pub fn last_received(&self) -> Instant {
+        *self.state.last_received.lock().unwrap()
+    }
+
+    /// Marks the peer down, or back up, as seen by the watchdog.
+    pub fn set_down(&self, down: bool) {
+        self.state.down.store(down, Ordering::Relaxed);
+    }
+
+    /// Whether new requests may be sent on this connection.
+    pub fn is_available(&self) -> bool {
+        !self.state.down.load(Ordering::Relaxed) && !self.is_closing() && !self.is_closed()
+    }
+
+    /// Whether the peer asked to disconnect with DPR.
+    pub fn is_closing(&self) -> bool {
+        self.state.closing.load(Ordering::Relaxed)
+    }
+
+    /// Whether nothing can be received any more, the peer closed the connection or sent
+    /// a message which could not be decoded.
+    pub fn is_closed(&self) -> bool {
+        self.state.closed.load(Ordering::Relaxed)
+    }
+
+    /// Number of peer-initiated DWRs answered on this connection.
+    pub fn watchdog_answered(&self) -> u64 {
+        self.state.watchdog_answered.load(Ordering::Relaxed)
+    }
+}
+
+fn tls_connector(tls: &options::Tls) -> Result<tokio_native_tls::TlsConnector> {
+    let mut builder = native_tls::TlsConnector::builder();
+
+    if let Some(ca_file) = &tls.ca_file {
+        let pem = read_file(ca_file)?;
+        for cert in native_tls::Certificate::stack_from_pem(&pem)? {
+            builder.add_root_certificate(cert);
+        }
+    }
+
+    match (&tls.cert_file, &tls.key_file) {
+        (Some(cert_file), Some(key_file)) => {
+            let cert = read_file(cert_file)?;
+            let key = read_file(key_file)?;
+            builder.identity(native_tls::Identity::from_pkcs8(&cert, &key)?);
+        }
+        (None, None) => {}
+        _ => {
+            return Err(Error::ClientError(
+                "TLS cert_file and key_file must be set together".into(),
+            ))
+        }
+    }
+
+    builder
+        .danger_accept_invalid_certs(!tls.verify)
+        .danger_accept_invalid_hostnames(!tls.verify);
+
+    Ok(tokio_native_tls::TlsConnector::from(builder.build()?))
+}
+
+fn read_file(filename: &str) -> Result<Vec<u8>> {
+    std::fs::read(filename)
+        .map_err(|e| Error::ClientError(format!("Failed to read '{}': {}", filename, e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diameter::dictionary;
+    use diameter::transport::{DiameterServer, DiameterServerConfig};
+    use diameter::ApplicationId;
+    use tempfile::TempDir;
+    use tokio::net::TcpListener;
+    use tokio::time::{self, Duration};
+
+    struct Certificates {
+        cert_file: String,
+        key_file: String,
+        identity: native_tls::Identity,
+        // The files are removed when dropped
+        _dir: TempDir,
+    }
+
+    fn generate_certificates() -> Certificates {
+        let dir = TempDir::new().unwrap();
+
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+        let cert_pem = certified.cert.pem();
+        let key_pem = certified.key_pair.serialize_pem();
+
+        let cert_file = dir.path().join("cert.pem");
+        let key_file = dir.path().join("key.pem");
+        std::fs::write(&cert_file, &cert_pem).unwrap();
+        std::fs::write(&key_file, &key_pem).unwrap();
+
+        Certificates {
+            cert_file: cert_file.to_string_lossy().into(),
+            key_file: key_file.to_string_lossy().into(),
+            identity: native_tls::Identity::from_pkcs8(cert_pem.as_bytes(), key_pem.as_bytes())
+                .unwrap(),
+            _dir: dir,
+        }
+    }
+
+    /// A port nothing listens on, for servers which bind by address.
+    fn free_port() -> u16 {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap().port()
+    }
+
+    /// Starts an answering TLS server, returns its port.
+    async fn start_tls_server(identity: native_tls::Identity) -> u16 {
+        let port = free_port();
+        let dict = Arc::new(Dictionary::new(&[&dictionary::DEFAULT_DICT_XML]));
+        let config = DiameterServerConfig {
+            native_tls: Some(identity),
+        };
+        let mut server = DiameterServer::new(&format!("127.0.0.1:{}", port), config)
+            .await
+            .unwrap();
+
+        let dict_ref = Arc::clone(&dict);
+        tokio::spawn(async move {
+            server
+                .listen(
+                    move |req| {
+                        let dict = Arc::clone(&dict_ref);
+                        async move {
+                            let mut res = DiameterMessage::new(
+                                req.get_command_code(),
+                                req.get_application_id(),
+                                req.get_flags() ^ flags::REQUEST,
+                                req.get_hop_by_hop_id(),
+                                req.get_end_to_end_id(),
+                                dict,
+                            );
+                            res.add_avp(264, None, M, Identity::new("server.localhost").into());
+                            res.add_avp(268, None, M, Unsigned32::new(2001).into());
+                            Ok(res)
+                        }
+                    },
+                    dict,
+                )
+                .await
+                .unwrap();
+        });
+        port
+    }
+
+    fn origin() -> Origin {
+        Origin {
+            host: "client.localhost".into(),
+            realm: "localhost".into(),
+        }
+    }
+
+    fn peer(port: u16) -> options::Peer {
+        options::Peer {
+            host: "localhost".into(),
+            port,
+            origin_host: None,
+            origin_realm: None,
+            destination_host: None,
+            destination_realm: None,
+            weight: 1,
+            connections: 1,
+        }
+    }
+
+    async fn exchange(peer: &options::Peer, tls: &options::Tls) -> Result<DiameterMessage> {
+        let dict = Arc::new(Dictionary::new(&[&dictionary::DEFAULT_DICT_XML]));
+        let (connection, mut handler) =
+            Connection::connect(peer, tls, Identifiers::new(None, 0)).await?;
+        let dict_ref = Arc::clone(&dict);
+        tokio::spawn(async move {
+            Connection::handle(&mut handler, dict_ref, origin()).await;
+        });
+
+        let mut cer = DiameterMessage::new(
+            CommandCode::CapabilitiesExchange,
+            ApplicationId::Common,
+            flags::REQUEST,
+            1,
+            1,
+            dict,
+        );
+        cer.add_avp(264, None, M, Identity::new("client.localhost").into());
+        connection.send_message(cer).await?.await
+    }
+
+    #[tokio::test]
+    async fn test_tls_verified_with_ca_file() {
+        let certs = generate_certificates();
+        let port = start_tls_server(certs.identity.clone()).await;
+
+        let tls = options::Tls {
+            enable: true,
+            ca_file: Some(certs.cert_file.clone()),
+            cert_file: Some(certs.cert_file.clone()),
+            key_file: Some(certs.key_file.clone()),
+            verify: true,
+            sni: Some("localhost".into()),
+            ..Default::default()
+        };
+        let cea = exchange(&peer(port), &tls).await.unwrap();
+        let result_code = cea.get_avp(268).unwrap().get_unsigned32().unwrap();
+        assert_eq!(result_code, 2001);
+    }
+
+    #[tokio::test]
+    async fn test_tls_rejects_unknown_ca() {
+        let certs = generate_certificates();
+        let port = start_tls_server(certs.identity.clone()).await;
+
+        let tls = options::Tls {
+            enable: true,
+            ..Default::default()
+        };
+        assert!(exchange(&peer(port), &tls).await.is_err());
+
+        // Same server is accepted once verification is turned off
+        let tls = options::Tls {
+            enable: true,
+            verify: false,
+            ..Default::default()
+        };
+        assert!(exchange(&peer(port), &tls).await.is_ok());
+    }
+
+    #[test]
+    fn test_tls_requires_cert_and_key() {
+        let tls = options::Tls {
+            enable: true,
+            cert_file: Some("client.pem".into()),
+            ..Default::default()
+        };
+        let err = tls_connector(&tls).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "TLS cert_file and key_file must be set together"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_answer_peer_watchdog() {
+        let dict = Arc::new(Dictionary::new(&[&dictionary::DEFAULT_DICT_XML]));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let (connection, mut handler) = Connection::connect(
+            &peer(port),
+            &options::Tls::default(),
+            Identifiers::new(None, 0),
+        )
+        .await
+        .unwrap();
+        let dict_ref = Arc::clone(&dict);
+        tokio::spawn(async move {
+            Connection::handle(&mut handler, dict_ref, origin()).await;
+        });
+
+        // Peer sends DWR over the accepted connection
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut dwr = DiameterMessage::new(
+            CommandCode::DeviceWatchdog,
+            ApplicationId::Common,
+            flags::REQUEST,
+            77,
+            88,
+            Arc::clone(&dict),
+        );
+        origin().add_to(&mut dwr);
+        Codec::encode(&mut stream, &dwr).await.unwrap();
+
+        let dwa = Codec::decode(&mut stream, dict).await.unwrap();
+        assert_eq!(dwa.get_command_code(), CommandCode::DeviceWatchdog);
+        assert_eq!(dwa.get_flags() & flags::REQUEST, 0);
+        assert_eq!(dwa.get_hop_by_hop_id(), 77);
+        assert_eq!(dwa.get_end_to_end_id(), 88);
+        let result_code = dwa.get_avp(RESULT_CODE).unwrap().get_unsigned32();
+        assert_eq!(result_code, Some(DIAMETER_SUCCESS));
+        assert_eq!(
+            identity_value(&dwa, ORIGIN_HOST),
+            Some("client.localhost".into())
+        );
+        assert_eq!(connection.watchdog_answered(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_answer_peer_disconnect() {
+        let dict = Arc::new(Dictionary::new(&[&dictionary::DEFAULT_DICT_XML]));
+        let (client, mut stream) = tokio::io::duplex(4096);
+        let (connection, mut handler) =
+            Connection::from_stream("peer", client, Identifiers::new(None, 0));
+        let dict_ref = Arc::clone(&dict);
+        tokio::spawn(async move {
+            Connection::handle(&mut handler, dict_ref, origin()).await;
+        });
+        assert!(connection.is_available());
+
+        let mut dpr = DiameterMessage::new(
+            CommandCode::DisconnectPeer,
+            ApplicationId::Common,
+            flags::REQUEST,
+            5,
+            6,
+            Arc::clone(&dict),
+        );
+        origin().add_to(&mut dpr);
+        Codec::encode(&mut stream, &dpr).await.unwrap();
+
+        let dpa = Codec::decode(&mut stream, Arc::clone(&dict)).await.unwrap();
+        assert_eq!(dpa.get_command_code(), CommandCode::DisconnectPeer);
+        assert_eq!(dpa.get_hop_by_hop_id(), 5);
+        assert!(connection.is_closing());
+        assert!(!connection.is_available());
+        // Closed after the DPA
+        assert!(Codec::decode(&mut stream, dict).await.is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_connection_closed_by_peer() {
+        let dict = Arc::new(Dictionary::new(&[&dictionary::DEFAULT_DICT_XML]));
+        let (client, stream) = tokio::io::duplex(4096);
+        let (connection, mut handler) =
+            Connection::from_stream("peer", client, Identifiers::new(None, 0));
+        let dict_ref = Arc::clone(&dict);
+        let handle = tokio::spawn(async move {
+            Connection::handle(&mut handler, dict_ref, origin()).await;
+        });
+
+        let request = |hop_by_hop| {
+            DiameterMessage::new(
+                CommandCode::CreditControl,
+                ApplicationId::CreditControl,
+                flags::REQUEST,
+                hop_by_hop,
+                hop_by_hop,
+                Arc::clone(&dict),
+            )
+        };
+        let response = connection.send_message(request(1)).await.unwrap();
+        drop(stream);
+        handle.await.unwrap();
+
+        // The request in flight fails without waiting for a timeout
+        let result = time::timeout(Duration::from_secs(1), response).await;
+        assert!(result.unwrap().is_err());
+        assert!(connection.is_closed());
+        assert!(!connection.is_available());
+        assert!(connection.send_message(request(2)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_disconnect_peer() {
+        let dict = Arc::new(Dictionary::new(&[&dictionary::DEFAULT_DICT_XML]));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        // Peer stand-in, answers DPR then expects the connection to be closed
+        let dict_ref = Arc::clone(&dict);
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let dpr = Codec::decode(&mut stream, Arc::clone(&dict_ref))
+                .await
+                .unwrap();
+            let cause = dpr
+                .get_avp(DISCONNECT_CAUSE)
+                .and_then(|avp| avp.get_enumerated())
+                .map(|cause| cause.value());
+            let dpa = answer(&dpr, DIAMETER_SUCCESS, &origin(), dict_ref.clone());
+            Codec::encode(&mut stream, &dpa).await.unwrap();
+            let closed = Codec::decode(&mut stream, dict_ref).await.is_err();
+            (dpr.get_command_code(), cause, closed)
+        });
+
+        let (connection, mut handler) = Connection::connect(
+            &peer(port),
+            &options::Tls::default(),
+            Identifiers::new(None, 0),
+        )
+        .await
+        .unwrap();
+        let dict_ref = Arc::clone(&dict);
+        tokio::spawn(async move {
+            Connection::handle(&mut handler, dict_ref, origin()).await;
+        });
+
+        let response = connection
+            .disconnect_peer(&origin(), options::DisconnectCause::Busy, dict)
+            .await
+            .unwrap();
+        let dpa = response.await.unwrap();
+        assert_eq!(dpa.get_command_code(), CommandCode::DisconnectPeer);
+        let result_code = dpa.get_avp(RESULT_CODE).unwrap().get_unsigned32();
+        assert_eq!(result_code, Some(DIAMETER_SUCCESS));
+        connection.close().await;
+
+        let (command_code, cause, closed) = server.await.unwrap();
+        assert_eq!(command_code, CommandCode::DisconnectPeer);
+        assert_eq!(cause, Some(1));
+        assert!(closed);
+    }
+
+    #[tokio::test]
+    async fn test_identifiers_awaiting_answer() {
+        let dict = Arc::new(Dictionary::new(&[&dictionary::DEFAULT_DICT_XML]));
+        // Peer which never answers
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (connection, _handler) = Connection::connect(
+            &peer(port),
+            &options::Tls::default(),
+            Identifiers::new(Some(7), 0),
+        )
+        .await
+        .unwrap();
+        let _stream = listener.accept().await.unwrap();
+
+        let request = |(hop_by_hop, end_to_end)| {
+            DiameterMessage::new(
+                CommandCode::CreditControl,
+                ApplicationId::CreditControl,
+                flags::REQUEST,
+                hop_by_hop,
+                end_to_end,
+                Arc::clone(&dict),
+            )
+        };
+        let mut expected = Identifiers::new(Some(7), 0);
+        let first = expected.next();
+        let second = expected.next();
+        let third = expected.next();
+
+        // The second identifiers are taken by a request awaiting its answer
+        let _answer = connection.send_message(request(second)).await.unwrap();
+        assert_eq!(connection.next_identifiers().await, first);
+        assert_eq!(connection.next_identifiers().await, third);
+
+        let error = connection
+            .send_message(request(second))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "Hop-by-Hop Identifier {} is already awaiting an answer",
+                second.0
+            )
+        );
+        connection.abandon(second.0).await;
+        assert!(connection.send_message(request(second)).await.is_ok());
+    }
+}
@@ -0,0 +1,809 @@
+//! Checks the options against the dictionary before anything is started,
+//! so every problem of a config is reported at once.
+use crate::assertion::Assertion;
+use crate::dictionary::EnumDictionary;
+use crate::global;
+use crate::options::{self, Options, ScenarioType};
+use crate::scenario;
+use diameter::avp::AvpType;
+use diameter::dictionary::Dictionary;
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Duration;
+
+/// Returns all problems found, each prefixed with where it was found.
+pub fn validate(
+    options: &Options,
+    dict: &Dictionary,
+    enums: &EnumDictionary,
+) -> Result<(), Vec<String>> {
+    let mut errors = vec![];
+
+    validate_load(options, &mut errors);
+    validate_peers(options, &mut errors);
+    let mut feeds = HashMap::new();
+    let variables = validate_variables(&options.globals, options.parallel, &mut feeds, &mut errors);
+    let captures = validate_captures(options, dict, &variables, &mut errors);
+    validate_scenarios(
+        options,
+        dict,
+        enums,
+        &variables,
+        &feeds,
+        &captures,
+        &mut errors,
+    );
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+fn validate_load(options: &Options, errors: &mut Vec<String>) {
+    if options.parallel == 0 {
+        errors.push("parallel: must be at least 1".into());
+    }
+    if options.target_rps == 0 {
+        errors.push("target_rps: must be at least 1".into());
+    }
+    if let options::BatchSize::Fixed(0) = options.batch_size {
+        errors.push("batch_size: must be at least 1".into());
+    }
+    if let options::BatchSize::Auto(value) = &options.batch_size {
+        if value != "Auto" {
+            errors.push(format!(
+                "batch_size: expected a number or 'Auto', got '{}'",
+                value
+            ));
+        }
+    }
+
+    let durations = [
+        ("call_timeout", options.call_timeout),
+        ("duration", options.duration),
+        ("watchdog.interval", options.watchdog.interval),
+        ("stats.interval", options.stats.interval),
+    ];
+    for (name, duration) in durations {
+        if duration == Duration::ZERO {
+            errors.push(format!("{}: must be greater than 0", name));
+        }
+    }
+    if options.duration < Duration::from_secs(1) && options.duration != Duration::ZERO {
+        errors.push("duration: must be at least 1s".into());
+    }
+}
+
+fn validate_peers(options: &Options, errors: &mut Vec<String>) {
+    if options.peers.is_empty() {
+        errors.push("peers: at least one peer is required".into());
+    }
+    for (i, peer) in options.peers.iter().enumerate() {
+        let path = format!("peers[{}] '{}'", i + 1, peer.address());
+        if peer.connections == 0 {
+            errors.push(format!("{}: connections must be at least 1", path));
+        }
+    }
+    if !options.peers.is_empty() && options.peers.iter().all(|peer| peer.weight == 0) {
+        errors.push("peers: at least one peer must have a weight above 0".into());
+    }
+
+    let tls = &options.tls;
+    if tls.transport != "TCP" {
+        errors.push(format!(
+            "tls.transport: {} is not supported, TLS runs over TCP only",
+            tls.transport
+        ));
+    }
+    if tls.enable {
+        if tls.cert_file.is_some() != tls.key_file.is_some() {
+            errors.push("tls: cert_file and key_file must be set together".into());
+        }
+        let files = [
+            ("tls.ca_file", &tls.ca_file),
+            ("tls.cert_file", &tls.cert_file),
+            ("tls.key_file", &tls.key_file),
+        ];
+        for (name, file) in files {
+            if let Some(file) = file {
+                if !Path::new(file).exists() {
+                    errors.push(format!("{}: file not found: {}", name, file));
+                }
+            }
+        }
+    }
+}
+
+/// Returns the variables by name, the feeds which could be read are added to `feeds`.
+fn validate_variables<'a>(
+    globals: &'a options::Global,
+    parallel: u32,
+    feeds: &mut HashMap<&'a str, global::Feed>,
+    errors: &mut Vec<String>,
+) -> HashMap<&'a str, &'a options::Variable> {
+    // Partitions are checked for the first runner, which gets the smallest one
+    let shared = global::Shared::new(globals, parallel as usize);
+    let mut variables = HashMap::new();
+    for map in &globals.variables {
+        for (name, variable) in map {
+            let path = format!("globals.variables '{}'", name);
+            let mut range_ok = true;
+            if variable.min > variable.max {
+                errors.push(format!(
+                    "{}: min {} is greater than max {}",
+                    path, variable.min, variable.max
+                ));
+                range_ok = false;
+            }
+            if variable.func == options::Function::IncrementalCounter && variable.step <= 0 {
+                errors.push(format!("{}: step must be greater than 0", path));
+                range_ok = false;
+            }
+            let cursor = matches!(
+                variable.func,
+                options::Function::IncrementalCounter
+                    | options::Function::RandomNumber
+                    | options::Function::CsvFeed
+                    | options::Function::IpAddress
+            );
+            if !cursor && variable.runners != options::Runners::Independent {
+                errors.push(format!(
+                    "{}: runners only applies to incremental_counter, random_number, \
+                     csv_feed and ip_address",
+                    path
+                ));
+            }
+            let built = match variable.func {
+                options::Function::IncrementalCounter | options::Function::RandomNumber
+                    if !range_ok =>
+                {
+                    None
+                }
+                options::Function::CsvFeed => match global::Feed::new(name, variable, &shared) {
+                    Ok(feed) => {
+                        feeds.insert(name.as_str(), feed);
+                        None
+                    }
+                    Err(e) => Some(e.to_string()),
+                },
+                _ => global::function(name, variable, &shared)
+                    .err()
+                    .map(|e| e.to_string()),
+            };
+            if let Some(e) = built {
+                errors.push(format!("{}: {}", path, e));
+            }
+            if name == scenario::REQUEST_NUMBER {
+                errors.push(format!(
+                    "{}: name is reserved for the built-in variable",
+                    path
+                ));
+            }
+            if variables.insert(name.as_str(), variable).is_some() {
+                errors.push(format!("{}: defined more than once", path));
+            }
+        }
+    }
+    variables
+}
+
+/// Checks the AVP paths of the captures, returns the captured variables with the index
+/// of the first scenario capturing them.
+fn validate_captures<'a>(
+    options: &'a Options,
+    dict: &Dictionary,
+    variables: &HashMap<&str, &options::Variable>,
+    errors: &mut Vec<String>,
+) -> HashMap<&'a str, usize> {
+    let mut captures = HashMap::new();
+    for (index, scenario) in options.scenarios.iter().enumerate() {
+        for (name, path) in &scenario.capture {
+            let context = format!("scenario '{}' > capture '{}'", scenario.name, name);
+            if variables.contains_key(name.as_str()) || name == scenario::REQUEST_NUMBER {
+                errors.push(format!("{}: name is already used by a variable", context));
+            }
+            if let Err(e) = scenario::AvpPath::new(path, dict) {
+                errors.push(format!("{}: {}", context, e));
+            }
+            captures.entry(name.as_str()).or_insert(index);
+        }
+    }
+    captures
+}
+
+/// What the values of the AVPs may refer to.
+struct Names<'a> {
+    variables: &'a HashMap<&'a str, &'a options::Variable>,
+    feeds: &'a HashMap<&'a str, global::Feed>,
+    /// Captured variables, whether captured by an earlier scenario
+    captures: HashMap<&'a str, bool>,
+}
+
+fn validate_scenarios(
+    options: &Options,
+    dict: &Dictionary,
+    enums: &EnumDictionary,
+    variables: &HashMap<&str, &options::Variable>,
+    feeds: &HashMap<&str, global::Feed>,
+    captures: &HashMap<&str, usize>,
+    errors: &mut Vec<String>,
+) {
+    match options.scenarios.first() {
+        None => errors.push("scenarios: at least one scenario is required".into()),
+        Some(first) if first.scenario_type != ScenarioType::Init => {
+            errors.push(format!(
+                "scenario '{}': the first scenario must be of type Init",
+                first.name
+            ));
+        }
+        Some(_) => {}
+    }
+    let repeating = options
+        .scenarios
+        .iter()
+        .filter(|s| s.scenario_type == ScenarioType::Repeating)
+        .count();
+    if !options.scenarios.is_empty() && repeating == 0 {
+        errors.push("scenarios: at least one Repeating scenario is required".into());
+    }
+
+    for (index, scenario) in options.scenarios.iter().enumerate() {
+        let path = format!("scenario '{}'", scenario.name);
+        // Only the answers of the scenarios before this one have been captured, a scenario
+        // sent several times has no answer yet for its first message
+        let names = Names {
+            variables,
+            feeds,
+            captures: captures
+                .iter()
+                .map(|(&name, &first)| (name, first < index))
+                .collect(),
+        };
+        if scenario.times == 0 {
+            errors.push(format!("{}: times must be at least 1", path));
+        }
+        let message = &scenario.message;
+        if dict.get_command_code_by_name(&message.command).is_none() {
+            errors.push(format!("{}: unknown command '{}'", path, message.command));
+        }
+        if dict
+            .get_application_id_by_name(&message.application)
+            .is_none()
+        {
+            errors.push(format!(
+                "{}: unknown application '{}'",
+                path, message.application
+            ));
+        }
+        let avps = &message.avps;
+        validate_avps(avps, &path, dict, enums, &names, errors);
+        for (i, assertion) in scenario.assertions.iter().enumerate() {
+            if let Err(e) = Assertion::new(assertion, dict) {
+                errors.push(format!("{} > assertion {}: {}", path, i + 1, e));
+            }
+        }
+    }
+}
+
+fn validate_avps(
+    avps: &[options::Avp],
+    path: &str,
+    dict: &Dictionary,
+    enums: &EnumDictionary,
+    names: &Names,
+    errors: &mut Vec<String>,
+) {
+    let Names {
+        variables,
+        feeds,
+        captures,
+    } = names;
+    for avp in avps {
+        let path = format!("{} > AVP '{}'", path, scenario::avp_label(avp));
+        let definition = match scenario::avp_definition(avp, dict) {
+            Ok(definition) => definition,
+            Err(e) => {
+                errors.push(format!("{}: {}", path, e));
+                continue;
+            }
+        };
+        if let Err(e) = scenario::avp_flags(&definition, &avp.flags) {
+            errors.push(format!("{}: {}", path, e));
+        }
+
+        match (&avp.value, definition.avp_type) {
+            (options::Value::Avp(children), AvpType::Grouped) => {
+                validate_avps(children, &path, dict, enums, names, errors);
+            }
+            (options::Value::Avp(_), avp_type) => {
+                errors.push(format!(
+                    "{}: {:?} AVP expects a value, not a list of AVPs",
+                    path, avp_type
+                ));
+            }
+            (options::Value::String(_), AvpType::Grouped) => {
+                errors.push(format!("{}: Grouped AVP expects a list of AVPs", path));
+            }
+            (options::Value::String(source), avp_type) => {
+                // Variables are replaced by a sample of what they generate, feeds by
+                // their first row, captured values and scripts are only known when run
+                let mut sample = source.clone();
+                let mut defined = true;
+                let mut unknown = false;
+                let is_feed = |name: &str| {
+                    variables
+                        .get(name)
+                        .is_some_and(|v| v.func == options::Function::CsvFeed)
+                };
+                for name in scenario::variable_names(source) {
+                    if name == scenario::REQUEST_NUMBER {
+                        sample = sample.replace(&format!("${{{}}}", name), "0");
+                        continue;
+                    }
+                    if is_feed(&name) {
+                        errors.push(format!(
+                            "{}: feed '{}' is used by column, as ${{{}.column}}",
+                            path, name, name
+                        ));
+                        defined = false;
+                        continue;
+                    }
+                    let feed = name.split_once('.').filter(|(feed, _)| is_feed(feed));
+                    match (variables.get(name.as_str()), feed) {
+                        (Some(variable), _) => match sample_value(&name, variable) {
+                            Some(value) => {
+                                sample = sample.replace(&format!("${{{}}}", name), &value);
+                            }
+                            None => unknown = true,
+                        },
+                        (None, Some((feed, column))) => {
+                            // A feed which could not be read is already reported
+                            let Some(feed) = feeds.get(feed) else {
+                                defined = false;
+                                continue;
+                            };
+                            match feed.column(column) {
+                                Some(column) => {
+                                    sample = sample
+                                        .replace(&format!("${{{}}}", name), feed.cell(0, column));
+                                }
+                                None => {
+                                    errors.push(format!(
+                                        "{}: column '{}' not found in feed '{}'",
+                                        path, column, feed.name
+                                    ));
+                                    defined = false;
+                                }
+                            }
+                        }
+                        (None, None) => match captures.get(name.as_str()) {
+                            Some(true) => unknown = true,
+                            Some(false) => {
+                                errors.push(format!(
+                                    "{}: variable '{}' is not captured by an earlier scenario",
+                                    path, name
+                                ));
+                                defined = false;
+                            }
+                            None => {
+                                errors.push(format!("{}: undefined variable '{}'", path, name));
+                                defined = false;
+                            }
+                        },
+                    }
+                }
+                if !defined || unknown {
+                    continue;
+                }
+                let items = enums.get(definition.code, definition.vendor_id);
+                if let Err(e) = scenario::named_avp_value(&sample, avp_type, items.as_deref()) {
+                    errors.push(format!(
+                        "{}: invalid {:?} value '{}': {}",
+                        path, avp_type, source, e
+                    ));
+                }
+            }
+        }
+    }
+}
+
+/// A value generated by `variable`, none when only known at run time.
+fn sample_value(name: &str, variable: &options::Variable) -> Option<String> {
+    if variable.func == options::Function::CustomScript {
+        return None;
+    }
+    let shared = global::Shared::new(&options::Global { variables: vec![] }, 1);
+    let (values, captured) = (HashMap::new(), HashMap::new());
+    let context = global::Context {
+        request_number: 0,
+        values: &values,
+        captured: &captured,
+        init: &captured,
+    };
+    global::function(name, variable, &shared)
+        .ok()?
+        .get(&context)
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diameter::dictionary;
+    use options::{Avp, Value, Variable};
+
+    fn valid() -> Options {
+        options::for_test(
+            r#"
+            globals = {
+                variables = {
+                    { COUNTER = { func = "incremental_counter", min = 1, max = 100, step = 1 } },
+                },
+            },
+            scenarios = {
+                {
+                    name = "CER",
+                    type = "Init",
+                    message = {
+                        command = "Capabilities-Exchange",
+                        application = "Base",
+                        avps = {
+                            { name = "Origin-Host", value = "host.example.com" },
+                            { name = "Host-IP-Address", value = "127.0.0.1" },
+                        },
+                    },
+                },
+                {
+                    name = "CCR-I",
+                    type = "Repeating",
+                    capture = { CC_TIME = "Granted-Service-Unit.CC-Time" },
+                    message = {
+                        command = "Credit-Control",
+                        application = "Charging Control",
+                        avps = {
+                            { name = "Session-Id", value = "ses;${REQUEST_NUMBER}" },
+                        },
+                    },
+                },
+                {
+                    name = "CCR",
+                    type = "Repeating",
+                    assertions = { { avp = "Result-Code", min = 2000, max = 2999 } },
+                    message = {
+                        command = "Credit-Control",
+                        application = "Charging Control",
+                        avps = {
+                            { name = "Session-Id", value = "ses;${COUNTER}" },
+                            { name = "CC-Time", value = "${CC_TIME}" },
+                            { name = "CC-Request-Number", value = "${COUNTER}" },
+                            { name = "CC-Request-Type", value = "${REQUEST_NUMBER}" },
+                            {
+                                name = "Subscription-Id",
+                                value = {
+                                    { name = "Subscription-Id-Type", value = "1" },
+                                    { name = "Subscription-Id-Data", value = "60123456789" },
+                                },
+                            },
+                        },
+                    },
+                },
+            },
+            "#,
+        )
+    }
+
+    fn dict() -> Dictionary {
+        Dictionary::new(&[&dictionary::DEFAULT_DICT_XML])
+    }
+
+    fn enums() -> EnumDictionary {
+        EnumDictionary::new(&[&dictionary::DEFAULT_DICT_XML])
+    }
+
+    fn check(options: &Options) -> Result<(), Vec<String>> {
+        validate(options, &dict(), &enums())
+    }
+
+    fn find<'a>(avps: &'a mut [Avp], name: &str) -> Option<&'a mut Avp> {
+        for avp in avps {
+            if avp.name == name {
+                return Some(avp);
+            }
+            if let Value::Avp(group) = &mut avp.value {
+                if let Some(avp) = find(group, name) {
+                    return Some(avp);
+                }
+            }
+        }
+        None
+    }
+
+    /// The AVP `name` of a scenario, grouped ones included.
+    fn avp<'a>(options: &'a mut Options, scenario: usize, name: &str) -> &'a mut Avp {
+        find(&mut options.scenarios[scenario].message.avps, name).unwrap()
+    }
+
+    fn set_value(options: &mut Options, scenario: usize, name: &str, value: &str) {
+        avp(options, scenario, name).value = Value::String(value.into());
+    }
+
+    fn counter(options: &mut Options) -> &mut Variable {
+        options.globals.variables[0].get_mut("COUNTER").unwrap()
+    }
+
+    #[test]
+    fn test_validate_ok() {
+        let mut options = valid();
+        assert_eq!(check(&options), Ok(()));
+
+        // Enumerated values by name
+        set_value(&mut options, 2, "Subscription-Id-Type", "END_USER_E164");
+        assert_eq!(check(&options), Ok(()));
+        set_value(&mut options, 2, "Subscription-Id-Type", "END_USER");
+        assert_eq!(
+            check(&options),
+            Err(vec![
+                "scenario 'CCR' > AVP 'Subscription-Id' > AVP 'Subscription-Id-Type': \
+                 invalid Enumerated value 'END_USER': unknown Enumerated value 'END_USER'"
+                    .to_string()
+            ])
+        );
+
+        // Raw AVPs outside the dictionary
+        let raw = |avp: Avp| {
+            let mut options = valid();
+            options.scenarios[2].message.avps.push(avp);
+            check(&options)
+        };
+        let address = Avp {
+            code: Some(1228),
+            vendor_id: Some(10415),
+            avp_type: Some("Address".into()),
+            value: Value::String("127.0.0.1".into()),
+            ..Default::default()
+        };
+        assert_eq!(raw(address), Ok(()));
+        let octets = Avp {
+            code: Some(99999),
+            value: Value::String("hex:0a0b".into()),
+            ..Default::default()
+        };
+        assert_eq!(raw(octets.clone()), Ok(()));
+        let unknown_type = Avp {
+            avp_type: Some("Unsigned".into()),
+            ..octets
+        };
+        assert_eq!(
+            raw(unknown_type),
+            Err(vec![
+                "scenario 'CCR' > AVP '99999': unknown type 'Unsigned'".to_string()
+            ])
+        );
+        let anonymous = Avp {
+            value: Value::String("1".into()),
+            ..Default::default()
+        };
+        assert_eq!(
+            raw(anonymous),
+            Err(vec![
+                "scenario 'CCR' > AVP '': needs a name or a code".to_string()
+            ])
+        );
+    }
+
+    #[test]
+    fn test_validate_collects_errors() {
+        let mut options = valid();
+        options.parallel = 0;
+        let counter = counter(&mut options);
+        counter.min = 100;
+        counter.max = 1;
+        avp(&mut options, 0, "Origin-Host").name = "Origin-Hostt".into();
+        set_value(&mut options, 0, "Host-IP-Address", "localhost");
+        let session_id = avp(&mut options, 2, "Session-Id");
+        session_id.value = Value::String("ses;${SESSION}".into());
+        session_id.flags.vendor = Some(true);
+        set_value(&mut options, 2, "CC-Request-Number", "x${COUNTER}");
+        avp(&mut options, 2, "Subscription-Id-Type").value = Value::Avp(vec![Avp {
+            name: "Subscription-Id-Data".into(),
+            value: Value::String("1".into()),
+            ..Default::default()
+        }]);
+
+        assert_eq!(
+            check(&options).unwrap_err(),
+            vec![
+                "parallel: must be at least 1",
+                "globals.variables 'COUNTER': min 100 is greater than max 1",
+                "scenario 'CER' > AVP 'Origin-Hostt': not found in dictionary",
+                "scenario 'CER' > AVP 'Host-IP-Address': invalid Address value 'localhost': \
+                 invalid IP address syntax",
+                "scenario 'CCR' > AVP 'Session-Id': the V bit needs a Vendor-Id, set vendor_id",
+                "scenario 'CCR' > AVP 'Session-Id': undefined variable 'SESSION'",
+                "scenario 'CCR' > AVP 'CC-Request-Number': invalid Unsigned32 value \
+                 'x${COUNTER}': invalid digit found in string",
+                "scenario 'CCR' > AVP 'Subscription-Id' > AVP 'Subscription-Id-Type': \
+                 Enumerated AVP expects a value, not a list of AVPs",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_tls_transport() {
+        let mut options = valid();
+        for transport in ["DTLS", "SCTP"] {
+            options.tls.transport = transport.into();
+            assert_eq!(
+                check(&options),
+                Err(vec![format!(
+                    "tls.transport: {} is not supported, TLS runs over TCP only",
+                    transport
+                )])
+            );
+        }
+    }
+
+    #[test]
+    fn test_validate_scenario_layout() {
+        let mut options = valid();
+        options.scenarios[0].scenario_type = ScenarioType::Repeating;
+        options.scenarios[0].times = 0;
+        for scenario in &mut options.scenarios[1..] {
+            scenario.message.command = "Credit-Controll".into();
+        }
+        let counter = counter(&mut options);
+        counter.func = options::Function::CustomScript;
+        counter.runners = options::Runners::Shared;
+        options.scenarios[1]
+            .capture
+            .insert("CC_TIME".into(), "CC-Time.Granted-Service-Unit".into());
+        options.scenarios[2].assertions[0].min = Some(3000.0);
+
+        assert_eq!(
+            check(&options).unwrap_err(),
+            vec![
+                "globals.variables 'COUNTER': runners only applies to incremental_counter, \
+                 random_number, csv_feed and ip_address",
+                "globals.variables 'COUNTER': custom_script needs either script or file",
+                "scenario 'CCR-I' > capture 'CC_TIME': AVP 'CC-Time' is not Grouped",
+                "scenario 'CER': the first scenario must be of type Init",
+                "scenario 'CER': times must be at least 1",
+                "scenario 'CCR-I': unknown command 'Credit-Controll'",
+                "scenario 'CCR': unknown command 'Credit-Controll'",
+                "scenario 'CCR' > assertion 1: min 3000 is greater than max 2999",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_capture_order() {
+        let mut options = valid();
+        let capture = options.scenarios[1].capture.remove("CC_TIME").unwrap();
+        options.scenarios[2]
+            .capture
+            .insert("CC_TIME".into(), capture);
+        let error = Err(vec![
+            "scenario 'CCR' > AVP 'CC-Time': variable 'CC_TIME' is not captured \
+             by an earlier scenario"
+                .to_string(),
+        ]);
+        assert_eq!(check(&options), error);
+        // Not for the first of the messages sent in a row either
+        options.scenarios[2].times = 2;
+        assert_eq!(check(&options), error);
+
+        // Captured from the CEA
+        options.scenarios[2].capture.clear();
+        options.scenarios[0]
+            .capture
+            .insert("CC_TIME".into(), "Origin-Host".into());
+        assert_eq!(check(&options), Ok(()));
+    }
+
+    #[test]
+    fn test_validate_partitions() {
+        let mut options = valid();
+        counter(&mut options).runners = options::Runners::Partitioned;
+        assert_eq!(check(&options), Ok(()));
+
+        options.parallel = 200;
+        assert_eq!(
+            check(&options),
+            Err(vec![
+                "globals.variables 'COUNTER': 100 values cannot be partitioned between 200 runners"
+                    .to_string()
+            ])
+        );
+    }
+
+    #[test]
+    fn test_validate_generators() {
+        let mut options = valid();
+        let generators = [
+            (
+                "IP",
+                Variable {
+                    func: options::Function::IpAddress,
+                    network: Some("10.0.0.0/8".into()),
+                    ..Default::default()
+                },
+            ),
+            (
+                "NOW",
+                Variable {
+                    func: options::Function::Timestamp,
+                    ..Default::default()
+                },
+            ),
+            (
+                "COLOR",
+                Variable {
+                    func: options::Function::Pick,
+                    ..Default::default()
+                },
+            ),
+        ];
+        options.globals.variables.splice(
+            0..0,
+            generators.map(|(name, variable)| [(name.to_string(), variable)].into()),
+        );
+        set_value(&mut options, 0, "Host-IP-Address", "${IP}");
+        set_value(&mut options, 2, "CC-Request-Number", "${NOW}");
+
+        let errors = check(&options).unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0], "globals.variables 'COLOR': pick needs values");
+        assert!(errors[1].starts_with(
+            "scenario 'CCR' > AVP 'CC-Request-Number': invalid Unsigned32 value '${NOW}'"
+        ));
+    }
+
+    #[test]
+    fn test_validate_feeds() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(&file, "msisdn,imsi\n60123456789,001010000000001\n").unwrap();
+        let feed = |file: &str, scope| Variable {
+            func: options::Function::CsvFeed,
+            file: Some(file.into()),
+            scope,
+            ..Default::default()
+        };
+        let mut options = valid();
+        let path = file.path().to_string_lossy();
+        options.globals.variables.splice(
+            0..0,
+            [
+                [("SUB".into(), feed(&path, options::Scope::Session))].into(),
+                [(
+                    "MISSING".into(),
+                    feed("missing.csv", options::Scope::Message),
+                )]
+                .into(),
+            ],
+        );
+        set_value(
+            &mut options,
+            2,
+            "Session-Id",
+            "ses;${SUB.imsi};${MISSING.imsi};${SUB}",
+        );
+        set_value(&mut options, 2, "CC-Request-Number", "${SUB.msisdn}");
+        set_value(&mut options, 2, "Subscription-Id-Data", "${SUB.iccid}");
+
+        assert_eq!(
+            check(&options).unwrap_err(),
+            vec![
+                "globals.variables 'MISSING': cannot read 'missing.csv': \
+                 No such file or directory (os error 2)",
+                "scenario 'CCR' > AVP 'Session-Id': feed 'SUB' is used by column, as ${SUB.column}",
+                // Type checked on the first row
+                "scenario 'CCR' > AVP 'CC-Request-Number': invalid Unsigned32 value \
+                 '${SUB.msisdn}': number too large to fit in target type",
+                "scenario 'CCR' > AVP 'Subscription-Id' > AVP 'Subscription-Id-Data': \
+                 column 'iccid' not found in feed 'SUB'",
+            ]
+        );
+    }
+}
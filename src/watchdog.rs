@@ -0,0 +1,264 @@
+//! Device-Watchdog procedure from RFC 3539 section 3.4.
+use crate::transport::{Connection, Origin, DIAMETER_SUCCESS, RESULT_CODE};
+use diameter::dictionary::Dictionary;
+use diameter::{flags, ApplicationId, CommandCode, DiameterMessage};
+use rand::Rng;
+use std::sync::Arc;
+use tokio::sync::oneshot;
+use tokio::time::{self, Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PeerState {
+    Okay,
+    Suspect,
+    Down,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WatchdogReport {
+    pub state: PeerState,
+    pub dwr_sent: u64,
+    pub failures: u64,
+    pub dwr_answered: u64,
+}
+
+/// Sends DWR on a connection once no traffic was received for Tw,
+/// and tracks the liveness of the peer from the DWAs.
+pub struct Watchdog {
+    address: String,
+    connection: Connection,
+    origin: Origin,
+    interval: Duration,
+    dict: Arc<Dictionary>,
+    state: PeerState,
+    dwr_sent: u64,
+    failures: u64,
+}
+
+impl Watchdog {
+    pub fn new(
+        address: &str,
+        connection: Connection,
+        origin: Origin,
+        interval: Duration,
+        dict: Arc<Dictionary>,
+    ) -> Self {
+        Watchdog {
+            address: address.into(),
+            connection,
+            origin,
+            interval,
+            dict,
+            state: PeerState::Okay,
+            dwr_sent: 0,
+            failures: 0,
+        }
+    }
+
+    /// Runs until `stop` fires, then reports what was observed.
+    ///
+    /// A peer DOWN has its connection closed, no DWR is sent on it any more.
+    pub async fn run(mut self, mut stop: oneshot::Receiver<()>) -> WatchdogReport {
+        loop {
+            if self.state == PeerState::Down {
+                log::error!("[{}] Closing the connection", self.address);
+                self.connection.close().await;
+                let _ = stop.await;
+                break;
+            }
+
+            let tw = self.tw();
+            let deadline = self.connection.last_received() + tw;
+            tokio::select! {
+                _ = &mut stop => break,
+                _ = time::sleep_until(deadline) => {}
+            }
+
+            // Traffic was received meanwhile, the peer is alive
+            if self.connection.last_received() + tw > Instant::now() {
+                self.set_state(PeerState::Okay);
+                continue;
+            }
+
+            let dwr = self.dwr().await;
+            let hop_by_hop = dwr.get_hop_by_hop_id();
+            let response = match self.connection.send_message(dwr).await {
+                Ok(response) => response,
+                Err(e) => {
+                    log::error!("[{}] Failed to send DWR; error: {}", self.address, e);
+                    self.on_failure();
+                    continue;
+                }
+            };
+            self.dwr_sent += 1;
+
+            tokio::select! {
+                _ = &mut stop => break,
+                result = time::timeout(tw, response) => match result {
+                    Ok(Ok(dwa)) if result_code(&dwa) == Some(DIAMETER_SUCCESS) => {
+                        self.set_state(PeerState::Okay);
+                    }
+                    Ok(Ok(dwa)) => {
+                        log::warn!(
+                            "[{}] DWA with Result-Code {:?}",
+                            self.address,
+                            result_code(&dwa)
+                        );
+                        self.on_failure();
+                    }
+                    Ok(Err(_)) | Err(_) => {
+                        self.connection.abandon(hop_by_hop).await;
+                        self.on_failure();
+                    }
+                },
+            }
+        }
+
+        WatchdogReport {
+            state: self.state,
+            dwr_sent: self.dwr_sent,
+            failures: self.failures,
+            dwr_answered: self.connection.watchdog_answered(),
+        }
+    }
+
+    /// Tw with a random jitter of up to 2 seconds, to avoid synchronized DWRs.
+    fn tw(&self) -> Duration {
+        let jitter = Duration::from_secs(2).min(self.interval / 4);
+        let offset = rand::thread_rng().gen_range(0..=jitter.as_millis() as u64 * 2);
+        self.interval - jitter + Duration::from_millis(offset)
+    }
+
+    async fn dwr(&self) -> DiameterMessage {
+        let (hop_by_hop, end_to_end) = self.connection.next_identifiers().await;
+        let mut dwr = DiameterMessage::new(
+            CommandCode::DeviceWatchdog,
+            ApplicationId::Common,
+            flags::REQUEST,
+            hop_by_hop,
+            end_to_end,
+            Arc::clone(&self.dict),
+        );
+        self.origin.add_to(&mut dwr);
+        dwr
+    }
+
+    fn on_failure(&mut self) {
+        self.failures += 1;
+        match self.state {
+            PeerState::Okay => self.set_state(PeerState::Suspect),
+            PeerState::Suspect | PeerState::Down => self.set_state(PeerState::Down),
+        }
+    }
+
+    fn set_state(&mut self, state: PeerState) {
+        if self.state == state {
+            return;
+        }
+        match state {
+            PeerState::Okay => log::info!("[{}] Peer is back to OKAY", self.address),
+            PeerState::Suspect => log::warn!("[{}] Peer is SUSPECT", self.address),
+            PeerState::Down => log::error!("[{}] Peer is DOWN", self.address),
+        }
+        // No new requests go to a peer down
+        self.connection.set_down(state == PeerState::Down);
+        self.state = state;
+    }
+}
+
+fn result_code(msg: &DiameterMessage) -> Option<u32> {
+    msg.get_avp(RESULT_CODE)?.get_unsigned32()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::identifiers::Identifiers;
+    use crate::transport;
+    use diameter::dictionary;
+    use diameter::transport::Codec;
+
+    fn origin() -> Origin {
+        Origin {
+            host: "client.localhost".into(),
+            realm: "localhost".into(),
+        }
+    }
+
+    /// Runs the watchdog against an in-memory peer for `duration` of paused time, returns
+    /// its report and the connection.
+    ///
+    /// The peer leaves the first `unanswered` DWRs without answer.
+    async fn run_watchdog(unanswered: usize, duration: Duration) -> (WatchdogReport, Connection) {
+        let dict = Arc::new(Dictionary::new(&[&dictionary::DEFAULT_DICT_XML]));
+        let (client, mut stream) = tokio::io::duplex(4096);
+
+        // Peer stand-in
+        let dict_ref = Arc::clone(&dict);
+        tokio::spawn(async move {
+            let mut received = 0;
+            while let Ok(dwr) = Codec::decode(&mut stream, Arc::clone(&dict_ref)).await {
+                received += 1;
+                if received > unanswered {
+                    let server = Origin {
+                        host: "server.localhost".into(),
+                        realm: "localhost".into(),
+                    };
+                    let dwa =
+                        transport::answer(&dwr, DIAMETER_SUCCESS, &server, Arc::clone(&dict_ref));
+                    Codec::encode(&mut stream, &dwa).await.unwrap();
+                }
+            }
+        });
+
+        let (connection, mut handler) =
+            Connection::from_stream("peer", client, Identifiers::new(None, 0));
+        let dict_ref = Arc::clone(&dict);
+        tokio::spawn(async move {
+            Connection::handle(&mut handler, dict_ref, origin()).await;
+        });
+
+        let watchdog = Watchdog::new(
+            "peer",
+            connection.clone(),
+            origin(),
+            Duration::from_millis(50),
+            dict,
+        );
+        let (stop_tx, stop_rx) = oneshot::channel();
+        let handle = tokio::spawn(watchdog.run(stop_rx));
+        time::sleep(duration).await;
+        stop_tx.send(()).unwrap();
+        (handle.await.unwrap(), connection)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_watchdog_okay() {
+        let (report, connection) = run_watchdog(0, Duration::from_millis(400)).await;
+        assert_eq!(report.state, PeerState::Okay);
+        assert_eq!(report.failures, 0);
+        assert!(report.dwr_sent >= 3);
+        assert!(connection.is_available());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_watchdog_peer_down() {
+        let (report, connection) = run_watchdog(usize::MAX, Duration::from_millis(400)).await;
+        assert_eq!(report.state, PeerState::Down);
+        // No DWR once DOWN
+        assert_eq!(report.failures, 2);
+        assert_eq!(report.dwr_sent, 2);
+        // Closed, which the peer follows with its own side
+        assert!(!connection.is_available());
+        assert!(connection.is_closed());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_watchdog_peer_back_up() {
+        // SUSPECT, then OKAY on the next DWA
+        let (report, connection) = run_watchdog(1, Duration::from_secs(1)).await;
+        assert_eq!(report.state, PeerState::Okay);
+        assert_eq!(report.failures, 1);
+        assert!(connection.is_available());
+    }
+}